@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use crate::{text::Page, token::Token};
+
+/// A position in a page that links can point to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Anchor {
+    pub id: String,
+    pub page: u32,
+    /// Byte offset of the anchor's token in the page's data
+    pub offset: u32,
+    /// Byte offset of the `IDEnd` closing the region an `IDStart` begins
+    pub end: Option<u32>,
+}
+
+/// Names the anchors of a page in order, so every backend gives them the
+/// same ids.
+///
+/// The format gives anchors numbers at most, so the ids are made up from the
+/// token and its number. An id that repeats on a page gets the number of the
+/// repetition appended, `id2`, `id2_2`, and so on.
+#[derive(Debug, Default)]
+pub struct AnchorIds {
+    word_anchors: u32,
+    seen: HashMap<String, u32>,
+}
+
+impl AnchorIds {
+    /// The id of the anchor the token sets, if it sets one.
+    pub fn id(&mut self, token: &Token) -> Option<String> {
+        let id = match token {
+            Token::IDStart(n) => format!("id{}", n),
+            Token::NodeNumber2(n) => format!("node{}", n),
+            Token::WordAnchor => {
+                self.word_anchors += 1;
+                format!("word{}", self.word_anchors)
+            }
+            _ => return None,
+        };
+
+        let count = self.seen.entry(id.clone()).or_default();
+        *count += 1;
+
+        if *count > 1 {
+            Some(format!("{}_{}", id, count))
+        } else {
+            Some(id)
+        }
+    }
+}
+
+/// The anchors of a volume, by page.
+#[derive(Debug, Default)]
+pub struct AnchorMap {
+    pages: HashMap<u32, Vec<Anchor>>,
+}
+
+impl AnchorMap {
+    pub fn collect<'a>(pages: impl IntoIterator<Item = &'a Page>) -> Self {
+        let mut map = Self::default();
+
+        for page in pages {
+            map.add(page);
+        }
+
+        map
+    }
+
+    pub fn add(&mut self, page: &Page) {
+        let mut ids = AnchorIds::default();
+        let number = page.number as u32;
+        let mut anchors: Vec<Anchor> = Vec::new();
+        // the anchor of the innermost open region with each number
+        let mut open_regions: HashMap<u8, usize> = HashMap::new();
+
+        for spanned in page.lex_spanned() {
+            if let Token::IDEnd(n) = spanned.token {
+                if let Some(i) = open_regions.remove(&n) {
+                    anchors[i].end = Some(spanned.span.start as u32);
+                }
+            }
+
+            if let Some(id) = ids.id(&spanned.token) {
+                if let Token::IDStart(n) = spanned.token {
+                    open_regions.insert(n, anchors.len());
+                }
+
+                anchors.push(Anchor {
+                    id,
+                    page: number,
+                    offset: spanned.span.start as u32,
+                    end: None,
+                });
+            }
+        }
+
+        if !anchors.is_empty() {
+            self.pages.insert(number, anchors);
+        }
+    }
+
+    pub fn on_page(&self, page: u32) -> &[Anchor] {
+        self.pages.get(&page).map_or(&[], |a| a.as_slice())
+    }
+
+    /// The anchor a link with the given name points to.
+    ///
+    /// Nothing in the format says which anchor a `PageLink`'s name refers to,
+    /// and most names are titles. The one pairing we make is a name that is a
+    /// number, which we take to be the `NodeNumber2` of that number on the
+    /// target page. Any other name links to the whole page.
+    pub fn resolve(&self, page: u32, name: &str) -> Option<&Anchor> {
+        let node = name.trim().parse::<u32>().ok()?;
+        let id = format!("node{}", node);
+
+        self.on_page(page).iter().find(|a| a.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_anchors() {
+        let tokens = [
+            Token::IDStart(2),
+            Token::Word { space_at_end: true, data: b"Wort".to_vec() },
+            Token::WordAnchor,
+            Token::IDEnd(2),
+            Token::NodeNumber2(77),
+            Token::WordAnchor,
+            Token::IDStart(2),
+            Token::EndOfPage,
+        ];
+        let page = Page::from_tokens(5, &tokens).unwrap();
+        let map = AnchorMap::collect([&page]);
+
+        let anchors = map
+            .on_page(5)
+            .iter()
+            .map(|a| (a.id.as_str(), a.offset, a.end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            anchors,
+            [
+                ("id2", 0, Some(9)),
+                ("word1", 8, None),
+                ("node77", 11, None),
+                ("word2", 16, None),
+                ("id2_2", 17, None),
+            ]
+        );
+        assert_eq!(map.resolve(5, " 77 ").map(|a| a.offset), Some(11));
+        assert!(map.resolve(5, "node77").is_none());
+        assert!(map.resolve(5, "2").is_none());
+        assert!(map.on_page(6).is_empty());
+    }
+}
@@ -1,21 +1,252 @@
-use encoding_rs::WINDOWS_1252;
+use std::{borrow::Cow, collections::HashMap};
 
-pub fn decode_string(data: &[u8], font: u8) -> String {
-    if data.len() == 1 {
-        match font {
-            1 => decode_windings(data),
+use encoding_rs::{WINDOWS_1252, WINDOWS_1255};
+use eyre::eyre;
+use once_cell::sync::Lazy;
+use unicode_normalization::UnicodeNormalization;
 
-            2 => decode_symbol(data),
+/// The ways the bytes of a word can map to characters, depending on its font.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum FontTable {
+    Vlado,
+    Wingdings,
+    Symbol,
+    ZapfDingbats,
+    Identity,
+}
+
+impl FontTable {
+    pub fn decode(self, data: &[u8]) -> String {
+        match self {
+            FontTable::Vlado => decode_vlado(data),
+            FontTable::Wingdings => decode_with(data, wingdings),
+            FontTable::Symbol => decode_with(data, symbol),
+            FontTable::ZapfDingbats => decode_with(data, zapf_dingbats),
+            FontTable::Identity => decode_identity(data),
+        }
+    }
+
+    /// Encode text so that `decode` gives it back, for the tables that can.
+    pub fn encode(self, s: &str) -> eyre::Result<Vec<u8>> {
+        match self {
+            FontTable::Vlado => encode_vlado(s),
+            FontTable::Symbol => encode_symbol(s),
+            _ => Err(eyre!("text can't be encoded in {:?}", self)),
+        }
+    }
+}
+
+/// Which table each `Token::Font` index decodes with.
+///
+/// Volumes mostly agree on the first few indices, anything else can be added
+/// with `--font-table`.
+#[derive(Clone, Debug)]
+pub struct FontRegistry {
+    tables: HashMap<u8, FontTable>,
+}
+
+impl Default for FontRegistry {
+    fn default() -> Self {
+        Self {
+            tables: HashMap::from([
+                (1, FontTable::Wingdings),
+                (2, FontTable::Symbol),
+                (3, FontTable::Identity),
+            ]),
+        }
+    }
+}
+
+impl FontRegistry {
+    pub fn with_overrides(overrides: &[(u8, FontTable)]) -> Self {
+        let mut registry = Self::default();
+        registry.tables.extend(overrides.iter().copied());
+        registry
+    }
 
-            3 => decode_identity(data),
+    pub fn table(&self, font: u8) -> FontTable {
+        self.tables.get(&font).copied().unwrap_or(FontTable::Vlado)
+    }
+
+    pub fn decode(&self, data: &[u8], font: u8) -> String {
+        self.table(font).decode(data)
+    }
+}
+
+/// Parse a `--font-table` override such as `4=zapf-dingbats`.
+pub fn parse_font_override(s: &str) -> eyre::Result<(u8, FontTable)> {
+    use clap::ValueEnum;
+
+    let (font, table) = s
+        .split_once('=')
+        .ok_or_else(|| eyre!("expected FONT=TABLE, got {:?}", s))?;
+
+    let table = FontTable::from_str(table.trim(), true).map_err(|e| eyre!(e))?;
+
+    Ok((font.trim().parse()?, table))
+}
+
+/// Decode a word with a single byte font, escapes for characters outside of
+/// it are still encoded like in `decode_vlado`.
+fn decode_with(data: &[u8], table: fn(u8) -> Option<char>) -> String {
+    let mut out = String::new();
+    let mut it = data.iter().copied();
 
-            _ => decode_vlado(data),
+    while let Some(a) = it.next() {
+        if a < 32 {
+            let Some(b) = it.next() else { break; };
+
+            out.push(unichar(a, b));
+        } else {
+            out.push(table(a).unwrap_or_else(|| WINDOWS_1252.decode(&[a]).0.chars().next().unwrap()));
         }
+    }
+
+    out
+}
+
+/// Apply the script region's font to text the lexer already decoded as
+/// Windows-1252 (`WordRest`, `WordIncomplete`).
+pub fn transliterate(s: &str, greek: bool, hebrew: bool) -> Cow<'_, str> {
+    if greek {
+        Cow::Owned(transliterate_greek(s))
+    } else if hebrew {
+        Cow::Owned(transliterate_hebrew(s))
     } else {
-        decode_vlado(data)
+        Cow::Borrowed(s)
     }
 }
 
+/// Decode a word set in the legacy Greek font.
+pub fn decode_greek(data: &[u8]) -> String {
+    transliterate_greek(&decode_vlado(data))
+}
+
+/// The Greek font places Greek glyphs on the ASCII letters, following Beta
+/// Code, with diacritics as separate glyphs after lower case letters and before
+/// upper case ones. We turn those into combining marks and let NFC compose them
+/// into polytonic codepoints.
+pub fn transliterate_greek(s: &str) -> String {
+    let mut out = String::new();
+    let mut pending_marks = String::new();
+    let mut it = s.chars().peekable();
+
+    while let Some(c) = it.next() {
+        if let Some(mark) = greek_mark(c) {
+            let next_letter = it.clone().find(|n| greek_mark(*n).is_none());
+
+            if next_letter.is_some_and(|n| n.is_ascii_uppercase()) {
+                pending_marks.push(mark);
+            } else {
+                out.push(mark);
+            }
+            continue;
+        }
+
+        let Some(letter) = greek_letter(c) else {
+            out.push(match c {
+                ':' => '\u{387}',
+                c => c,
+            });
+            continue;
+        };
+
+        let ends_word = !it
+            .peek()
+            .is_some_and(|n| n.is_ascii_alphabetic() || greek_mark(*n).is_some());
+
+        let letter = if letter == 'σ' && ends_word {
+            'ς'
+        } else {
+            letter
+        };
+
+        out.push(letter);
+        out.push_str(&pending_marks);
+        pending_marks.clear();
+    }
+
+    out.nfc().collect()
+}
+
+fn greek_letter(c: char) -> Option<char> {
+    let lower = match c.to_ascii_lowercase() {
+        'a' => 'α',
+        'b' => 'β',
+        'g' => 'γ',
+        'd' => 'δ',
+        'e' => 'ε',
+        'v' => 'ϝ',
+        'z' => 'ζ',
+        'h' => 'η',
+        'q' => 'θ',
+        'i' => 'ι',
+        'k' => 'κ',
+        'l' => 'λ',
+        'm' => 'μ',
+        'n' => 'ν',
+        'c' => 'ξ',
+        'o' => 'ο',
+        'p' => 'π',
+        'r' => 'ρ',
+        's' => 'σ',
+        'j' => 'ς',
+        't' => 'τ',
+        'u' => 'υ',
+        'f' => 'φ',
+        'x' => 'χ',
+        'y' => 'ψ',
+        'w' => 'ω',
+        _ => return None,
+    };
+
+    if c.is_ascii_uppercase() {
+        lower.to_uppercase().next()
+    } else {
+        Some(lower)
+    }
+}
+
+fn greek_mark(c: char) -> Option<char> {
+    Some(match c {
+        ')' => '\u{313}',
+        '(' => '\u{314}',
+        '/' => '\u{301}',
+        '\\' => '\u{300}',
+        '=' => '\u{342}',
+        '+' => '\u{308}',
+        '|' => '\u{345}',
+        _ => return None,
+    })
+}
+
+/// Decode a word set in the Hebrew font.
+pub fn decode_hebrew(data: &[u8]) -> String {
+    transliterate_hebrew(&decode_vlado(data))
+}
+
+/// The Hebrew font is laid out like the Windows-1255 code page, so we undo the
+/// Windows-1252 decoding of the upper half and decode those bytes as 1255.
+/// Text is stored in logical order, so nothing needs reversing here.
+pub fn transliterate_hebrew(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            let mut buf = [0u8; 4];
+            let (bytes, _, unmappable) = WINDOWS_1252.encode(c.encode_utf8(&mut buf));
+
+            match *bytes {
+                [b] if !unmappable && b >= 0x80 => WINDOWS_1255
+                    .decode_without_bom_handling(&[b])
+                    .0
+                    .chars()
+                    .next()
+                    .unwrap_or(c),
+                _ => c,
+            }
+        })
+        .collect()
+}
+
 fn decode_vlado(data: &[u8]) -> String {
     let mut out = String::new();
     let mut it = data.iter().copied();
@@ -35,6 +266,44 @@ fn decode_vlado(data: &[u8]) -> String {
     }
 }
 
+/// Encode text so that `decode_vlado` gives it back, for building pages.
+///
+/// Characters outside of Windows-1252 use the first escape that `unichar`
+/// decodes to them. Some have none: U+0700..U+1200 and, apart from the private
+/// use characters `unichar` remaps, most of everything past U+2800.
+pub fn encode_vlado(s: &str) -> eyre::Result<Vec<u8>> {
+    static ESCAPES: Lazy<HashMap<char, [u8; 2]>> = Lazy::new(|| {
+        let mut escapes = HashMap::new();
+
+        for a in 0..32 {
+            for b in 0..=255 {
+                escapes.entry(unichar(a, b)).or_insert([a, b]);
+            }
+        }
+
+        escapes
+    });
+
+    let mut out = Vec::with_capacity(s.len());
+
+    for c in s.chars() {
+        let mut buf = [0u8; 4];
+        let (bytes, _, unmappable) = WINDOWS_1252.encode(c.encode_utf8(&mut buf));
+
+        match *bytes {
+            [b] if !unmappable && b >= 32 => out.push(b),
+            _ => {
+                let escape = ESCAPES
+                    .get(&c)
+                    .ok_or_else(|| eyre!("{:?} (U+{:04X}) can't be encoded", c, c as u32))?;
+                out.extend_from_slice(escape);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
 fn unichar(a: u8, b: u8) -> char {
     let (a, b) = (a as u16, b as u16);
     let x = b.wrapping_sub(a + 1)
@@ -57,247 +326,621 @@ fn decode_identity(data: &[u8]) -> String {
     String::from_utf8_lossy(data).to_string()
 }
 
-fn decode_symbol(data: &[u8]) -> String {
-    data.iter()
-        .copied()
-        .filter_map(|x| match x {
-            45 => Some('\u{ad}'),
-            200 => Some('\u{222a}'),
-            x => char::try_from(x).ok(),
+/// Adobe's Symbol encoding.
+///
+/// The bracket and brace pieces map to their Unicode equivalents rather than
+/// Adobe's private use codepoints, the serif and sans variants of the
+/// registered/copyright/trademark signs both map to the plain ones.
+fn symbol(b: u8) -> Option<char> {
+    Some(match b {
+        // Digibib uses this as a hyphen, not as the minus sign Adobe has here
+        0x2d => '\u{ad}',
+        0x22 => '∀',
+        0x24 => '∃',
+        0x27 => '∋',
+        0x2a => '∗',
+        0x40 => '≅',
+        0x41 => 'Α',
+        0x42 => 'Β',
+        0x43 => 'Χ',
+        0x44 => 'Δ',
+        0x45 => 'Ε',
+        0x46 => 'Φ',
+        0x47 => 'Γ',
+        0x48 => 'Η',
+        0x49 => 'Ι',
+        0x4a => 'ϑ',
+        0x4b => 'Κ',
+        0x4c => 'Λ',
+        0x4d => 'Μ',
+        0x4e => 'Ν',
+        0x4f => 'Ο',
+        0x50 => 'Π',
+        0x51 => 'Θ',
+        0x52 => 'Ρ',
+        0x53 => 'Σ',
+        0x54 => 'Τ',
+        0x55 => 'Υ',
+        0x56 => 'ς',
+        0x57 => 'Ω',
+        0x58 => 'Ξ',
+        0x59 => 'Ψ',
+        0x5a => 'Ζ',
+        0x5c => '∴',
+        0x5e => '⊥',
+        0x60 => '\u{203e}',
+        0x61 => 'α',
+        0x62 => 'β',
+        0x63 => 'χ',
+        0x64 => 'δ',
+        0x65 => 'ε',
+        0x66 => 'φ',
+        0x67 => 'γ',
+        0x68 => 'η',
+        0x69 => 'ι',
+        0x6a => 'ϕ',
+        0x6b => 'κ',
+        0x6c => 'λ',
+        0x6d => 'μ',
+        0x6e => 'ν',
+        0x6f => 'ο',
+        0x70 => 'π',
+        0x71 => 'θ',
+        0x72 => 'ρ',
+        0x73 => 'σ',
+        0x74 => 'τ',
+        0x75 => 'υ',
+        0x76 => 'ϖ',
+        0x77 => 'ω',
+        0x78 => 'ξ',
+        0x79 => 'ψ',
+        0x7a => 'ζ',
+        0x7e => '∼',
+        0x20..=0x7d => char::from(b),
+        0xa0 => '€',
+        0xa1 => 'ϒ',
+        0xa2 => '′',
+        0xa3 => '≤',
+        0xa4 => '⁄',
+        0xa5 => '∞',
+        0xa6 => 'ƒ',
+        0xa7 => '♣',
+        0xa8 => '♦',
+        0xa9 => '♥',
+        0xaa => '♠',
+        0xab => '↔',
+        0xac => '←',
+        0xad => '↑',
+        0xae => '→',
+        0xaf => '↓',
+        0xb0 => '°',
+        0xb1 => '±',
+        0xb2 => '″',
+        0xb3 => '≥',
+        0xb4 => '×',
+        0xb5 => '∝',
+        0xb6 => '∂',
+        0xb7 => '•',
+        0xb8 => '÷',
+        0xb9 => '≠',
+        0xba => '≡',
+        0xbb => '≈',
+        0xbc => '…',
+        0xbd => '⏐',
+        0xbe => '⎯',
+        0xbf => '↵',
+        0xc0 => 'ℵ',
+        0xc1 => 'ℑ',
+        0xc2 => 'ℜ',
+        0xc3 => '℘',
+        0xc4 => '⊗',
+        0xc5 => '⊕',
+        0xc6 => '∅',
+        0xc7 => '∩',
+        0xc8 => '∪',
+        0xc9 => '⊃',
+        0xca => '⊇',
+        0xcb => '⊄',
+        0xcc => '⊂',
+        0xcd => '⊆',
+        0xce => '∈',
+        0xcf => '∉',
+        0xd0 => '∠',
+        0xd1 => '∇',
+        0xd2 | 0xe2 => '®',
+        0xd3 | 0xe3 => '©',
+        0xd4 | 0xe4 => '™',
+        0xd5 => '∏',
+        0xd6 => '√',
+        0xd7 => '⋅',
+        0xd8 => '¬',
+        0xd9 => '∧',
+        0xda => '∨',
+        0xdb => '⇔',
+        0xdc => '⇐',
+        0xdd => '⇑',
+        0xde => '⇒',
+        0xdf => '⇓',
+        0xe0 => '◊',
+        0xe1 => '〈',
+        0xe5 => '∑',
+        0xe6 => '⎛',
+        0xe7 => '⎜',
+        0xe8 => '⎝',
+        0xe9 => '⎡',
+        0xea => '⎢',
+        0xeb => '⎣',
+        0xec => '⎧',
+        0xed => '⎨',
+        0xee => '⎩',
+        0xef => '⎪',
+        0xf1 => '〉',
+        0xf2 => '∫',
+        0xf3 => '⌠',
+        0xf4 => '⎮',
+        0xf5 => '⌡',
+        0xf6 => '⎞',
+        0xf7 => '⎟',
+        0xf8 => '⎠',
+        0xf9 => '⎤',
+        0xfa => '⎥',
+        0xfb => '⎦',
+        0xfc => '⎫',
+        0xfd => '⎬',
+        0xfe => '⎭',
+        _ => return None,
+    })
+}
+
+/// The inverse of `symbol`. The registered/copyright/trademark signs encode to
+/// 0xd2..=0xd4, their duplicates at 0xe2..=0xe4 are never written.
+fn encode_symbol(s: &str) -> eyre::Result<Vec<u8>> {
+    static BYTES: Lazy<HashMap<char, u8>> = Lazy::new(|| {
+        let mut bytes = HashMap::new();
+
+        for b in 0x20..=0xff {
+            if let Some(c) = symbol(b) {
+                bytes.entry(c).or_insert(b);
+            }
+        }
+
+        bytes
+    });
+
+    s.chars()
+        .map(|c| {
+            BYTES
+                .get(&c)
+                .copied()
+                .ok_or_else(|| eyre!("{:?} (U+{:04X}) isn't in the Symbol font", c, c as u32))
         })
         .collect()
 }
 
-fn decode_windings(data: &[u8]) -> String {
-    let decode_byte = |b: u8| -> char {
-        match b {
-            32 => '\u{20}',
-            33 => '\u{1f589}',
-            34 => '\u{2702}',
-            35 => '\u{2701}',
-            36 => '\u{1f453}',
-            37 => '\u{1f56d}',
-            38 => '\u{1f56e}',
-            39 => '\u{1f56f}',
-            40 => '\u{1f57f}',
-            41 => '\u{2706}',
-            42 => '\u{1f582}',
-            43 => '\u{1f583}',
-            44 => '\u{1f4ea}',
-            45 => '\u{1f4eb}',
-            46 => '\u{1f4ec}',
-            47 => '\u{1f4ed}',
-            48 => '\u{1f5c0}',
-            49 => '\u{1f5c1}',
-            50 => '\u{1f5ce}',
-            51 => '\u{1f5cf}',
-            52 => '\u{1f5d0}',
-            53 => '\u{1f5c4}',
-            54 => '\u{23f3}',
-            55 => '\u{1f5ae}',
-            56 => '\u{1f5b0}',
-            57 => '\u{1f5b2}',
-            58 => '\u{1f5b3}',
-            59 => '\u{1f5b4}',
-            60 => '\u{1f5ab}',
-            61 => '\u{1f5ac}',
-            62 => '\u{2707}',
-            63 => '\u{270d}',
-            64 => '\u{1f58e}',
-            65 => '\u{270c}',
-            66 => '\u{1f58f}',
-            67 => '\u{1f44d}',
-            68 => '\u{1f44e}',
-            69 => '\u{261c}',
-            70 => '\u{261e}',
-            71 => '\u{261c}',
-            72 => '\u{1f597}',
-            73 => '\u{1f590}',
-            74 => '\u{263a}',
-            75 => '\u{1f610}',
-            76 => '\u{2639}',
-            77 => '\u{1f4a3}',
-            78 => '\u{1f571}',
-            79 => '\u{1f3f3}',
-            80 => '\u{1f3f1}',
-            81 => '\u{2708}',
-            82 => '\u{263c}',
-            83 => '\u{1f322}',
-            84 => '\u{2744}',
-            85 => '\u{1f546}',
-            86 => '\u{271e}',
-            87 => '\u{1f548}',
-            88 => '\u{2720}',
-            89 => '\u{2721}',
-            90 => '\u{262a}',
-            91 => '\u{262f}',
-            92 => '\u{1f549}',
-            93 => '\u{2638}',
-            94 => '\u{2648}',
-            95 => '\u{2649}',
-            96 => '\u{264a}',
-            97 => '\u{264b}',
-            98 => '\u{264c}',
-            99 => '\u{264d}',
-            100 => '\u{264e}',
-            101 => '\u{264f}',
-            102 => '\u{2650}',
-            103 => '\u{2651}',
-            104 => '\u{2652}',
-            105 => '\u{2653}',
-            106 => '\u{1f670}',
-            107 => '\u{1f675}',
-            108 => '\u{26ab}',
-            109 => '\u{1f53e}',
-            110 => '\u{25fc}',
-            111 => '\u{1f78f}',
-            112 => '\u{1f790}',
-            113 => '\u{2751}',
-            114 => '\u{2752}',
-            115 => '\u{1f79f}',
-            116 => '\u{29eb}',
-            117 => '\u{25c6}',
-            118 => '\u{2756}',
-            119 => '\u{1f799}',
-            120 => '\u{2327}',
-            121 => '\u{2bb9}',
-            122 => '\u{2318}',
-            123 => '\u{1f3f5}',
-            124 => '\u{1f3f6}',
-            125 => '\u{1f676}',
-            126 => '\u{1f677}',
-            // 127 unused
-            128 => '\u{1f10b}',
-            129 => '\u{2780}',
-            130 => '\u{2781}',
-            131 => '\u{2782}',
-            132 => '\u{2783}',
-            133 => '\u{2784}',
-            134 => '\u{2785}',
-            135 => '\u{2786}',
-            136 => '\u{2787}',
-            137 => '\u{2788}',
-            138 => '\u{2789}',
-            139 => '\u{1f10c}',
-            140 => '\u{278a}',
-            141 => '\u{278b}',
-            142 => '\u{278c}',
-            143 => '\u{278d}',
-            144 => '\u{278e}',
-            145 => '\u{278f}',
-            146 => '\u{2790}',
-            147 => '\u{2791}',
-            148 => '\u{2792}',
-            149 => '\u{2793}',
-            150 => '\u{1f662}',
-            151 => '\u{1f660}',
-            152 => '\u{1f661}',
-            153 => '\u{1f663}',
-            154 => '\u{1f666}',
-            155 => '\u{1f664}',
-            156 => '\u{1f665}',
-            157 => '\u{1f667}',
-            158 => '\u{2219}',
-            159 => '\u{2022}',
-            160 => '\u{2b1d}',
-            161 => '\u{2b58}',
-            162 => '\u{1f786}',
-            163 => '\u{1f788}',
-            164 => '\u{1f78a}',
-            165 => '\u{1f78b}',
-            166 => '\u{1f53f}',
-            167 => '\u{25aa}',
-            168 => '\u{1f78e}',
-            169 => '\u{1f7c0}',
-            170 => '\u{1f7c1}',
-            171 => '\u{2605}',
-            172 => '\u{1f7cb}',
-            173 => '\u{1f7cf}',
-            174 => '\u{1f7d3}',
-            175 => '\u{1f7d1}',
-            176 => '\u{2bd0}',
-            177 => '\u{2316}',
-            178 => '\u{2bce}',
-            179 => '\u{2bcf}',
-            180 => '\u{2bd1}',
-            181 => '\u{272a}',
-            182 => '\u{2730}',
-            183 => '\u{1f550}',
-            184 => '\u{1f551}',
-            185 => '\u{1f552}',
-            186 => '\u{1f553}',
-            187 => '\u{1f554}',
-            188 => '\u{1f555}',
-            189 => '\u{1f556}',
-            190 => '\u{1f557}',
-            191 => '\u{1f558}',
-            192 => '\u{1f559}',
-            193 => '\u{1f55a}',
-            194 => '\u{1f55b}',
-            195 => '\u{2bb0}',
-            196 => '\u{2bb1}',
-            197 => '\u{2bb2}',
-            198 => '\u{2bb3}',
-            199 => '\u{2bb4}',
-            200 => '\u{2bb5}',
-            201 => '\u{2bb6}',
-            202 => '\u{2bb7}',
-            203 => '\u{1f66a}',
-            204 => '\u{1f66b}',
-            205 => '\u{1f655}',
-            206 => '\u{1f654}',
-            207 => '\u{1f657}',
-            208 => '\u{1f656}',
-            209 => '\u{1f650}',
-            210 => '\u{1f651}',
-            211 => '\u{1f652}',
-            212 => '\u{1f653}',
-            213 => '\u{232b}',
-            214 => '\u{2326}',
-            215 => '\u{2b98}',
-            216 => '\u{2b9a}',
-            217 => '\u{2b99}',
-            218 => '\u{2b9b}',
-            219 => '\u{2b88}',
-            220 => '\u{2b8a}',
-            221 => '\u{2b89}',
-            222 => '\u{2b8b}',
-            223 => '\u{1f868}',
-            224 => '\u{1f86a}',
-            225 => '\u{1f869}',
-            226 => '\u{1f86b}',
-            227 => '\u{1f86c}',
-            228 => '\u{1f86d}',
-            229 => '\u{1f86f}',
-            230 => '\u{1f86e}',
-            231 => '\u{1f878}',
-            232 => '\u{1f87a}',
-            233 => '\u{1f879}',
-            234 => '\u{1f87b}',
-            235 => '\u{1f87c}',
-            236 => '\u{1f87d}',
-            237 => '\u{1f87f}',
-            238 => '\u{1f87e}',
-            239 => '\u{21e6}',
-            240 => '\u{21e8}',
-            241 => '\u{21e7}',
-            242 => '\u{21e9}',
-            243 => '\u{2b04}',
-            244 => '\u{21f3}',
-            245 => '\u{2b01}',
-            246 => '\u{2b00}',
-            247 => '\u{2b03}',
-            248 => '\u{2b02}',
-            249 => '\u{1f8ac}',
-            250 => '\u{1f8ad}',
-            251 => '\u{1f5f6}',
-            252 => '\u{2713}',
-            253 => '\u{1f5f7}',
-            254 => '\u{1f5f9}',
-            255 => '\u{229e}', // similar
-            x => panic!("bad wingding: {}", x),
-        }
+fn wingdings(b: u8) -> Option<char> {
+    Some(match b {
+        32 => '\u{20}',
+        33 => '\u{1f589}',
+        34 => '\u{2702}',
+        35 => '\u{2701}',
+        36 => '\u{1f453}',
+        37 => '\u{1f56d}',
+        38 => '\u{1f56e}',
+        39 => '\u{1f56f}',
+        40 => '\u{1f57f}',
+        41 => '\u{2706}',
+        42 => '\u{1f582}',
+        43 => '\u{1f583}',
+        44 => '\u{1f4ea}',
+        45 => '\u{1f4eb}',
+        46 => '\u{1f4ec}',
+        47 => '\u{1f4ed}',
+        48 => '\u{1f5c0}',
+        49 => '\u{1f5c1}',
+        50 => '\u{1f5ce}',
+        51 => '\u{1f5cf}',
+        52 => '\u{1f5d0}',
+        53 => '\u{1f5c4}',
+        54 => '\u{23f3}',
+        55 => '\u{1f5ae}',
+        56 => '\u{1f5b0}',
+        57 => '\u{1f5b2}',
+        58 => '\u{1f5b3}',
+        59 => '\u{1f5b4}',
+        60 => '\u{1f5ab}',
+        61 => '\u{1f5ac}',
+        62 => '\u{2707}',
+        63 => '\u{270d}',
+        64 => '\u{1f58e}',
+        65 => '\u{270c}',
+        66 => '\u{1f58f}',
+        67 => '\u{1f44d}',
+        68 => '\u{1f44e}',
+        69 => '\u{261c}',
+        70 => '\u{261e}',
+        71 => '\u{261c}',
+        72 => '\u{1f597}',
+        73 => '\u{1f590}',
+        74 => '\u{263a}',
+        75 => '\u{1f610}',
+        76 => '\u{2639}',
+        77 => '\u{1f4a3}',
+        78 => '\u{1f571}',
+        79 => '\u{1f3f3}',
+        80 => '\u{1f3f1}',
+        81 => '\u{2708}',
+        82 => '\u{263c}',
+        83 => '\u{1f322}',
+        84 => '\u{2744}',
+        85 => '\u{1f546}',
+        86 => '\u{271e}',
+        87 => '\u{1f548}',
+        88 => '\u{2720}',
+        89 => '\u{2721}',
+        90 => '\u{262a}',
+        91 => '\u{262f}',
+        92 => '\u{1f549}',
+        93 => '\u{2638}',
+        94 => '\u{2648}',
+        95 => '\u{2649}',
+        96 => '\u{264a}',
+        97 => '\u{264b}',
+        98 => '\u{264c}',
+        99 => '\u{264d}',
+        100 => '\u{264e}',
+        101 => '\u{264f}',
+        102 => '\u{2650}',
+        103 => '\u{2651}',
+        104 => '\u{2652}',
+        105 => '\u{2653}',
+        106 => '\u{1f670}',
+        107 => '\u{1f675}',
+        108 => '\u{26ab}',
+        109 => '\u{1f53e}',
+        110 => '\u{25fc}',
+        111 => '\u{1f78f}',
+        112 => '\u{1f790}',
+        113 => '\u{2751}',
+        114 => '\u{2752}',
+        115 => '\u{1f79f}',
+        116 => '\u{29eb}',
+        117 => '\u{25c6}',
+        118 => '\u{2756}',
+        119 => '\u{1f799}',
+        120 => '\u{2327}',
+        121 => '\u{2bb9}',
+        122 => '\u{2318}',
+        123 => '\u{1f3f5}',
+        124 => '\u{1f3f6}',
+        125 => '\u{1f676}',
+        126 => '\u{1f677}',
+        // 127 unused
+        128 => '\u{1f10b}',
+        129 => '\u{2780}',
+        130 => '\u{2781}',
+        131 => '\u{2782}',
+        132 => '\u{2783}',
+        133 => '\u{2784}',
+        134 => '\u{2785}',
+        135 => '\u{2786}',
+        136 => '\u{2787}',
+        137 => '\u{2788}',
+        138 => '\u{2789}',
+        139 => '\u{1f10c}',
+        140 => '\u{278a}',
+        141 => '\u{278b}',
+        142 => '\u{278c}',
+        143 => '\u{278d}',
+        144 => '\u{278e}',
+        145 => '\u{278f}',
+        146 => '\u{2790}',
+        147 => '\u{2791}',
+        148 => '\u{2792}',
+        149 => '\u{2793}',
+        150 => '\u{1f662}',
+        151 => '\u{1f660}',
+        152 => '\u{1f661}',
+        153 => '\u{1f663}',
+        154 => '\u{1f666}',
+        155 => '\u{1f664}',
+        156 => '\u{1f665}',
+        157 => '\u{1f667}',
+        158 => '\u{2219}',
+        159 => '\u{2022}',
+        160 => '\u{2b1d}',
+        161 => '\u{2b58}',
+        162 => '\u{1f786}',
+        163 => '\u{1f788}',
+        164 => '\u{1f78a}',
+        165 => '\u{1f78b}',
+        166 => '\u{1f53f}',
+        167 => '\u{25aa}',
+        168 => '\u{1f78e}',
+        169 => '\u{1f7c0}',
+        170 => '\u{1f7c1}',
+        171 => '\u{2605}',
+        172 => '\u{1f7cb}',
+        173 => '\u{1f7cf}',
+        174 => '\u{1f7d3}',
+        175 => '\u{1f7d1}',
+        176 => '\u{2bd0}',
+        177 => '\u{2316}',
+        178 => '\u{2bce}',
+        179 => '\u{2bcf}',
+        180 => '\u{2bd1}',
+        181 => '\u{272a}',
+        182 => '\u{2730}',
+        183 => '\u{1f550}',
+        184 => '\u{1f551}',
+        185 => '\u{1f552}',
+        186 => '\u{1f553}',
+        187 => '\u{1f554}',
+        188 => '\u{1f555}',
+        189 => '\u{1f556}',
+        190 => '\u{1f557}',
+        191 => '\u{1f558}',
+        192 => '\u{1f559}',
+        193 => '\u{1f55a}',
+        194 => '\u{1f55b}',
+        195 => '\u{2bb0}',
+        196 => '\u{2bb1}',
+        197 => '\u{2bb2}',
+        198 => '\u{2bb3}',
+        199 => '\u{2bb4}',
+        200 => '\u{2bb5}',
+        201 => '\u{2bb6}',
+        202 => '\u{2bb7}',
+        203 => '\u{1f66a}',
+        204 => '\u{1f66b}',
+        205 => '\u{1f655}',
+        206 => '\u{1f654}',
+        207 => '\u{1f657}',
+        208 => '\u{1f656}',
+        209 => '\u{1f650}',
+        210 => '\u{1f651}',
+        211 => '\u{1f652}',
+        212 => '\u{1f653}',
+        213 => '\u{232b}',
+        214 => '\u{2326}',
+        215 => '\u{2b98}',
+        216 => '\u{2b9a}',
+        217 => '\u{2b99}',
+        218 => '\u{2b9b}',
+        219 => '\u{2b88}',
+        220 => '\u{2b8a}',
+        221 => '\u{2b89}',
+        222 => '\u{2b8b}',
+        223 => '\u{1f868}',
+        224 => '\u{1f86a}',
+        225 => '\u{1f869}',
+        226 => '\u{1f86b}',
+        227 => '\u{1f86c}',
+        228 => '\u{1f86d}',
+        229 => '\u{1f86f}',
+        230 => '\u{1f86e}',
+        231 => '\u{1f878}',
+        232 => '\u{1f87a}',
+        233 => '\u{1f879}',
+        234 => '\u{1f87b}',
+        235 => '\u{1f87c}',
+        236 => '\u{1f87d}',
+        237 => '\u{1f87f}',
+        238 => '\u{1f87e}',
+        239 => '\u{21e6}',
+        240 => '\u{21e8}',
+        241 => '\u{21e7}',
+        242 => '\u{21e9}',
+        243 => '\u{2b04}',
+        244 => '\u{21f3}',
+        245 => '\u{2b01}',
+        246 => '\u{2b00}',
+        247 => '\u{2b03}',
+        248 => '\u{2b02}',
+        249 => '\u{1f8ac}',
+        250 => '\u{1f8ad}',
+        251 => '\u{1f5f6}',
+        252 => '\u{2713}',
+        253 => '\u{1f5f7}',
+        254 => '\u{1f5f9}',
+        255 => '\u{229e}', // similar
+        _ => return None,
+    })
+}
+
+/// Adobe's ZapfDingbats, which the Unicode dingbats block is laid out after.
+fn zapf_dingbats(b: u8) -> Option<char> {
+    let c = match b {
+        0x20 => 0x20,
+        // glyphs that were already encoded elsewhere when the block was made
+        0x25 => 0x260e,
+        0x2a => 0x261b,
+        0x2b => 0x261e,
+        0x48 => 0x2605,
+        0x6c => 0x25cf,
+        0x6e => 0x25a0,
+        0x73 => 0x25b2,
+        0x74 => 0x25bc,
+        0x75 => 0x25c6,
+        0x77 => 0x25d7,
+        0xa8 => 0x2663,
+        0xa9 => 0x2666,
+        0xaa => 0x2665,
+        0xab => 0x2660,
+        0xd5 => 0x2192,
+        0xd6 => 0x2194,
+        0xd7 => 0x2195,
+        0xac..=0xb5 => 0x2460 + (b - 0xac) as u32,
+        0x21..=0x7e => 0x2700 + (b - 0x20) as u32,
+        0x80..=0x8d => 0x2768 + (b - 0x80) as u32,
+        0xa1..=0xa7 | 0xb6..=0xd4 | 0xd8..=0xef | 0xf1..=0xfe => 0x2700 + (b - 0x40) as u32,
+        _ => return None,
     };
 
-    data.iter().copied().map(decode_byte).collect::<String>()
+    char::from_u32(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Font index, encoded word and what it should decode to.
+    const CORPUS: &[(u8, &[u8], &str)] = &[
+        (0, b"Stra\xdfe", "Straße"),
+        (0, &[b'K', 2, 0x54, b'r'], "Kőr"),
+        (0, &[5, 0x3c, 5, 0x3d], "жз"),
+        (1, &[0x4a], "\u{263a}"),
+        (1, &[0x4a, 0x4c], "\u{263a}\u{2639}"),
+        (1, &[0x21, 2, 0x54], "\u{1f589}ő"),
+        (2, b"a+b", "α+β"),
+        (2, &[0x53, 0xb3, 0x30], "Σ≥0"),
+        (2, &[0xc8, 0x2d], "∪\u{ad}"),
+        (3, b"abc", "abc"),
+        (7, b"abc", "abc"),
+    ];
+
+    #[test]
+    fn corpus() {
+        let fonts = FontRegistry::default();
+
+        for (font, data, expected) in CORPUS {
+            assert_eq!(&fonts.decode(data, *font), expected, "font {} {:?}", font, data);
+        }
+    }
+
+    /// Symbol bytes decode to the characters Adobe's table gives them, and
+    /// every defined byte decodes to a distinct character outside the PUA,
+    /// except for the serif/sans duplicates.
+    #[test]
+    fn symbol_mappings() {
+        // from Adobe's SYMBOL.TXT
+        let known = [
+            (0x22, '∀'),
+            (0x24, '∃'),
+            (0x41, 'Α'),
+            (0x44, 'Δ'),
+            (0x61, 'α'),
+            (0x66, 'φ'),
+            (0x6a, 'ϕ'),
+            (0xa5, '∞'),
+            (0xae, '→'),
+            (0xb3, '≥'),
+            (0xb9, '≠'),
+            (0xd6, '√'),
+            (0xe5, '∑'),
+            (0xf2, '∫'),
+        ];
+        for (b, c) in known {
+            assert_eq!(symbol(b), Some(c), "{:#x}", b);
+        }
+
+        let mut seen = std::collections::HashSet::new();
+
+        for b in 0x20..=0xffu8 {
+            let Some(c) = symbol(b) else {
+                assert!(matches!(b, 0x7f..=0x9f | 0xf0 | 0xff), "{:#x} unmapped", b);
+                continue;
+            };
+
+            assert!(!('\u{e000}'..='\u{f8ff}').contains(&c), "{:#x} maps to the PUA", b);
+            assert!(seen.insert(c) || matches!(b, 0xe2..=0xe4), "{:#x} is a duplicate", b);
+        }
+
+        assert_eq!(FontTable::Symbol.decode(b"\xe5\xf2\xd6\xa5"), "∑∫√∞");
+    }
+
+    #[test]
+    fn symbol_round_trip() {
+        for b in (0x20..=0xffu8).filter(|&b| symbol(b).is_some()) {
+            let decoded = FontTable::Symbol.decode(&[b]);
+            let encoded = FontTable::Symbol.encode(&decoded).unwrap();
+
+            // the serif and sans signs come back as the plain ones
+            let expected = if matches!(b, 0xe2..=0xe4) { b - 0x10 } else { b };
+            assert_eq!(encoded, [expected], "{:#x}", b);
+        }
+
+        assert!(FontTable::Symbol.encode("ä").is_err());
+        assert!(FontTable::Wingdings.encode("a").is_err());
+    }
+
+    #[test]
+    fn vlado_encoding() {
+        assert_eq!(encode_vlado("Kőr").unwrap(), [b'K', 2, 0x54, b'r']);
+        assert_eq!(encode_vlado("Straße").unwrap(), b"Stra\xdfe");
+        assert!(encode_vlado("\u{1100}").is_err());
+        assert!(encode_vlado("漢").is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn vlado_round_trip(s in "\\PC*") {
+            if let Ok(encoded) = encode_vlado(&s) {
+                proptest::prop_assert_eq!(decode_vlado(&encoded), s);
+            }
+        }
+
+        #[test]
+        fn vlado_round_trip_encodable(
+            s in proptest::collection::vec(
+                proptest::prop_oneof![
+                    proptest::char::range(' ', '\u{6ff}'),
+                    proptest::char::range('\u{1200}', '\u{27ff}'),
+                    proptest::char::range('\u{e000}', '\u{e0ff}'),
+                ],
+                0..32,
+            )
+        ) {
+            let s = s.into_iter().collect::<String>();
+            let encoded = encode_vlado(&s).unwrap();
+            proptest::prop_assert_eq!(decode_vlado(&encoded), s);
+        }
+    }
+
+    #[test]
+    fn font_overrides() {
+        let fonts = FontRegistry::with_overrides(&[parse_font_override("4=zapf-dingbats").unwrap()]);
+
+        assert_eq!(fonts.table(4), FontTable::ZapfDingbats);
+        assert_eq!(fonts.decode(&[0x48, 0xac, 0x21, 0xd8], 4), "★①✁➘");
+        assert_eq!(fonts.table(1), FontTable::Wingdings);
+
+        assert!(parse_font_override("4").is_err());
+        assert!(parse_font_override("x=symbol").is_err());
+        assert!(parse_font_override("4=comic-sans").is_err());
+    }
+
+    #[test]
+    fn greek_table() {
+        assert_eq!(decode_greek(b"abgdezhqiklmncoprstufxyw"), "αβγδεζηθικλμνξοπρστυφχψω");
+        assert_eq!(decode_greek(b"ABGDEZHQIKLMNCOPRSTUFXYW"), "ΑΒΓΔΕΖΗΘΙΚΛΜΝΞΟΠΡΣΤΥΦΧΨΩ");
+        assert_eq!(decode_greek(b"v j"), "ϝ ς");
+
+        // σ becomes ς only at the end of a word
+        assert_eq!(decode_greek(b"lo/gos"), "λόγος");
+        assert_eq!(decode_greek(b"sofo/s, kai/"), "σοφός, καί");
+
+        // marks follow lower case letters and precede upper case ones
+        assert_eq!(decode_greek(b"a) a( a/ a\\ a= i+ a|"), "ἀ ἁ ά ὰ ᾶ ϊ ᾳ");
+        assert_eq!(decode_greek(b")/Anqrwpos"), "Ἄνθρωπος");
+        assert_eq!(decode_greek(b"(O"), "Ὁ");
+        assert_eq!(decode_greek(b"th=|"), "τῇ");
+
+        assert_eq!(decode_greek(b"a)lhqei/as:"), "ἀληθείας·");
+        assert_eq!(decode_greek(b"1, 2."), "1, 2.");
+    }
+
+    #[test]
+    fn hebrew_table() {
+        let letters = (0xe0..=0xfa).collect::<Vec<u8>>();
+        assert_eq!(decode_hebrew(&letters), "אבגדהוזחטיךכלםמןנסעףפץצקרשת");
+
+        // stored in logical order
+        assert_eq!(decode_hebrew(&[0xf9, 0xec, 0xe5, 0xed]), "שלום");
+
+        // points, the sheqel sign and maqaf from the upper half, ASCII as is
+        assert_eq!(decode_hebrew(&[0xe1, 0xcc, 0xc8, 0xf8, 0xc0]), "ב\u{5bc}\u{5b8}ר\u{5b0}");
+        assert_eq!(decode_hebrew(&[0xa4, 0xce]), "₪־");
+        assert_eq!(decode_hebrew(b"1, 2."), "1, 2.");
+        assert_eq!(transliterate_hebrew("1"), "1");
+    }
 }
@@ -4,6 +4,7 @@ use std::fmt::Write;
 
 use once_cell::sync::Lazy;
 use regex::Regex;
+use tracing::warn;
 
 #[derive(Clone, Debug, PartialEq, Eq, Default)]
 pub struct Style {
@@ -19,21 +20,127 @@ pub struct Style {
     pub color_gray: bool,
     pub no_justification: bool,
     pub alignment: Option<&'static str>,
+    pub greek: bool,
+    pub hebrew: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum AnnotationKind {
+    /// An editor's correction of the text
+    Correction,
+    /// A lemma of the critical apparatus
+    Lemma,
+}
+
+/// The start of an annotation span.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    pub kind: AnnotationKind,
+    /// The id of the correction or apparatus entry
+    pub target: u64,
+    pub lemma: Option<String>,
+}
+
+/// Where a link to a page points.
+#[derive(Clone, Debug)]
+pub struct PageRef<'a> {
+    pub page: u32,
+    /// The output file the page is in, if it isn't part of the current one
+    pub external: Option<&'a str>,
+    /// The anchor on the page, for links to a precise position
+    pub anchor: Option<&'a str>,
+    /// The name a `PageLink` gives its target
+    pub label: Option<&'a str>,
+    /// The TOC entry the page belongs to
+    pub entry: &'a TocItem,
 }
 
 pub trait Encoder {
     fn chunk(&mut self, s: &str, style: &Style);
     fn link(&mut self, url: &str, content: &str);
-    fn pageref(&mut self, page: u32);
+    fn pageref(&mut self, target: &PageRef);
+    /// A position on the page that links can point to.
+    fn anchor(&mut self, id: &str);
     fn searchword(&mut self, s: &str);
+    /// Text that only goes into the plain text used for search, such as the
+    /// start of a word hyphenated on the previous page.
+    fn plain(&mut self, s: &str);
+    /// Text that is shown but left out of the plain text, such as the start of
+    /// a word hyphenated onto the next page, whose plain text gets all of it.
+    fn display(&mut self, s: &str, style: &Style);
+    /// Lists can nest. Everything up to the next `list_item` or `list_end`
+    /// belongs to the current item.
+    fn list_start(&mut self);
+    fn list_item(&mut self);
+    fn list_end(&mut self);
+    /// Tables are made of rows of cells. Text goes into the current cell,
+    /// and `x` is the cell's horizontal position if one was given.
+    fn table_start(&mut self);
+    fn table_row(&mut self);
+    fn table_cell(&mut self, x: Option<u16>);
+    fn table_end(&mut self);
+    /// Annotations span the text up to the matching `annotation_end`.
+    fn annotation_start(&mut self, annotation: &Annotation);
+    fn annotation_end(&mut self, annotation: &Annotation);
+    /// A reference to the entry with this index in the volume's bibliography.
+    fn bib_index(&mut self, index: u32);
+    /// A running head, which lasts to the end of its line. Its text isn't part
+    /// of the page's body.
+    fn header_start(&mut self);
+    fn header_end(&mut self);
 }
 
-use crate::{decoding, toc::TocItem, token::Token};
+use crate::{
+    anchor::AnchorIds,
+    decoding::{self, FontRegistry},
+    links::{self, BrokenLink, Links},
+    normalize::Normalizer,
+    split::{Location, WorkMap},
+    toc::TocItem,
+    token::Token,
+};
+
+/// Volume-wide settings and lookups shared by every page.
+pub struct Context<'a> {
+    pub works: &'a WorkMap,
+    pub fonts: &'a FontRegistry,
+    pub normalizer: &'a Normalizer,
+    pub links: &'a Links<'a>,
+}
+
+/// Hyphenation state that continues from one page onto the next, so words
+/// split across a page break are joined.
+#[derive(Debug, Default)]
+pub struct Carry {
+    /// The first part of a word hyphenated at the end of the previous page
+    pub fragment: Option<String>,
+    pub separating_ck: bool,
+    pub word_incomplete: bool,
+    /// Annotations still open at the end of the previous page
+    annotations: Vec<Annotation>,
+}
 
 struct State<'a, E> {
     encoder: &'a mut E,
+    normalizer: &'a Normalizer,
     queued_link: Option<(String, String)>,
+    /// Text not yet passed to the encoder, so that a run in one style is
+    /// normalized as a whole and marks can compose with the letter before them
+    pending_text: Option<(String, Style)>,
+    /// The last word, held back until we know whether the next one continues it
+    pending_word: Option<(String, Style)>,
+    /// A fragment carried over from the previous page, not yet joined
+    carried: Option<String>,
     font_idx: u8,
+    list_depth: usize,
+    in_header: bool,
+    in_table: bool,
+    /// The position of the next table cell
+    cell_x: Option<u16>,
+    /// The apparatus entry the next lemma belongs to
+    apparatus: Option<u64>,
+    annotations: Vec<Annotation>,
+    anchor_ids: AnchorIds,
     word_incomplete: bool,
     had_carriage_return: bool,
     add_hyphen_at_eol: bool,
@@ -47,11 +154,22 @@ struct State<'a, E> {
 }
 
 impl<'a, E: Encoder> State<'a, E> {
-    fn new(encoder: &'a mut E) -> Self {
+    fn new(encoder: &'a mut E, normalizer: &'a Normalizer) -> Self {
         Self {
             encoder,
+            normalizer,
             queued_link: None,
+            pending_text: None,
+            pending_word: None,
+            carried: None,
             font_idx: 0,
+            list_depth: 0,
+            in_header: false,
+            in_table: false,
+            cell_x: None,
+            apparatus: None,
+            annotations: Vec::new(),
+            anchor_ids: AnchorIds::default(),
             word_incomplete: false,
             had_carriage_return: false,
             add_hyphen_at_eol: false,
@@ -74,30 +192,207 @@ impl<'a, E: Encoder> State<'a, E> {
     fn hyphen(&self) -> bool {
         self.add_hyphen_at_eol || self.add_hyphen_at_eol_separating_ck || self.add_invisible_hyphen
     }
-}
 
-impl<'a, E: Encoder> Write for State<'a, E> {
-    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+    fn emit(&mut self, s: &str, style: &Style) {
         if let Some(link) = &mut self.queued_link {
             link.0.push_str(s);
-            Ok(())
+            return;
+        }
+
+        match &mut self.pending_text {
+            Some((text, pending_style)) if pending_style == style => text.push_str(s),
+            _ => {
+                self.flush_text();
+                self.pending_text = Some((s.to_owned(), style.clone()));
+            }
+        }
+    }
+
+    fn flush_text(&mut self) {
+        if let Some((text, style)) = self.pending_text.take() {
+            self.encoder.chunk(&self.normalizer.apply(&text), &style);
+        }
+    }
+
+    /// The encoder, for anything other than text, which has to come after the
+    /// text written so far.
+    fn out(&mut self) -> &mut E {
+        self.flush_text();
+        self.encoder
+    }
+
+    fn start_annotation(&mut self, annotation: Annotation) {
+        self.out().annotation_start(&annotation);
+        self.annotations.push(annotation);
+    }
+
+    fn end_annotation(&mut self, kind: AnnotationKind) {
+        if let Some(idx) = self.annotations.iter().rposition(|a| a.kind == kind) {
+            let annotation = self.annotations.remove(idx);
+            self.out().annotation_end(&annotation);
+        }
+    }
+
+    /// End the table row, and the table too if no cells follow.
+    fn end_table_row(&mut self, rest: &[Token]) {
+        if row_cells(rest) > 0 {
+            self.out().table_row();
         } else {
-            self.encoder.chunk(s, &self.current_style);
-            Ok(())
+            self.out().table_end();
+            self.in_table = false;
+        }
+    }
+
+    /// Write a link to a page, or warn and leave it out if the page doesn't exist.
+    /// Links to pages that were filtered out are left out too, there's nothing
+    /// for them to point at.
+    fn pageref(&mut self, ctx: &Context, source: usize, page: u32, label: Option<&str>) {
+        if ctx.works.locate(page) == Location::Excluded {
+            return;
+        }
+
+        match ctx.links.resolve(page, label) {
+            Some(target) => self.out().pageref(&PageRef {
+                page,
+                external: ctx.works.external(page),
+                anchor: target.anchor,
+                label,
+                entry: target.entry,
+            }),
+            None => warn!(
+                "{}",
+                BrokenLink {
+                    source,
+                    target: page,
+                    label: label.map(|l| l.to_owned()),
+                }
+            ),
+        }
+    }
+
+    fn end_header(&mut self) {
+        if self.in_header {
+            self.out().header_end();
+            self.in_header = false;
+        }
+    }
+
+    fn flush_word(&mut self) {
+        if let Some((word, style)) = self.pending_word.take() {
+            self.emit(&word, &style);
+        }
+    }
+
+    /// Write a word without a space after it, joining it to the previous one
+    /// if that was hyphenated.
+    fn write_word(&mut self, s: &str) {
+        // historical German hyphenation splits ck as k-k
+        let split_ck = self.add_hyphen_at_eol_separating_ck && s.starts_with('k');
+        let ck = |fragment: &mut String| {
+            if split_ck && fragment.ends_with('k') {
+                fragment.pop();
+                fragment.push('c');
+            }
+        };
+
+        if let Some(mut fragment) = self.carried.take() {
+            ck(&mut fragment);
+            let fragment = self.normalizer.apply(&fragment).into_owned();
+            self.out().plain(&fragment);
+        }
+
+        if let Some((mut word, style)) = self.pending_word.take() {
+            if self.hyphen() {
+                ck(&mut word);
+            }
+
+            self.emit(&word, &style);
         }
+
+        self.pending_word = Some((s.to_owned(), self.current_style.clone()));
+    }
+}
+
+impl<'a, E: Encoder> Write for State<'a, E> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.flush_word();
+
+        let style = self.current_style.clone();
+        self.emit(s, &style);
+
+        Ok(())
     }
 }
 
 
+fn ends_row(t: &Token) -> bool {
+    matches!(t, Token::HardCarriageReturn | Token::SetY(_) | Token::EndOfPage)
+}
+
+/// The number of cells in the table row at the start of `rest`.
+///
+/// `TD` starts a cell, and a line break or `SetY` ends the row.
+pub fn row_cells(rest: &[Token]) -> usize {
+    rest.iter()
+        .take_while(|t| !ends_row(t))
+        .filter(|t| matches!(t, Token::TD))
+        .count()
+}
+
+/// The number of cells in the widest row of the table starting at `rest`.
+pub fn table_columns(mut rest: &[Token]) -> usize {
+    let mut columns = 0;
+
+    loop {
+        let cells = row_cells(rest);
+
+        if cells == 0 {
+            return columns;
+        }
+
+        columns = columns.max(cells);
+
+        match rest.iter().position(ends_row) {
+            Some(end) if rest[end] != Token::EndOfPage => rest = &rest[end + 1..],
+            _ => return columns,
+        }
+    }
+}
+
 pub fn encode_page(
     tocitem: &TocItem,
     page_number: usize,
     lexed: &[Token],
+    ctx: &Context,
+    carry: &mut Carry,
     encoder: &mut impl Encoder,
 ) -> eyre::Result<()> {
-    let mut state = State::new(encoder);
+    let mut state = State::new(encoder, ctx.normalizer);
+
+    state.add_hyphen_at_eol_separating_ck = carry.separating_ck;
+    state.add_invisible_hyphen = carry.fragment.is_some() && !carry.separating_ck;
+    state.word_incomplete = carry.word_incomplete;
+    state.carried = carry.fragment.take();
+
+    for annotation in carry.annotations.drain(..) {
+        state.start_annotation(annotation);
+    }
+
+    for (i, t) in lexed.iter().enumerate() {
+        let continues_word = matches!(
+            t,
+            Token::Word { .. }
+                | Token::HypenAtEol
+                | Token::InvisibleHyphen
+                | Token::HyphenCK
+                | Token::SoftCarriageReturn
+                | Token::EndOfPage
+        );
+
+        if !continues_word {
+            state.flush_word();
+        }
 
-    for t in lexed {
         match t {
             Token::Blanks(number) => {
                 for _ in 0..*number {
@@ -105,7 +400,13 @@ pub fn encode_page(
                 }
             }
             Token::Word { space_at_end, data } => {
-                let s = decoding::decode_string(data, state.font_idx);
+                let s = if state.current_style.greek {
+                    decoding::decode_greek(data)
+                } else if state.current_style.hebrew {
+                    decoding::decode_hebrew(data)
+                } else {
+                    ctx.fonts.decode(data, state.font_idx)
+                };
 
                 let s = if !state.hyphen() {
                     s.trim_end().trim_end_matches('-')
@@ -115,10 +416,8 @@ pub fn encode_page(
 
                 if state.word_incomplete {
                     state.word_incomplete = false;
-                } else {
-                    if s.len() > 0 {
-                        write!(state, "{}", s)?;
-                    }
+                } else if !s.is_empty() {
+                    state.write_word(s);
                 }
 
                 state.reset_hyphens();
@@ -130,6 +429,14 @@ pub fn encode_page(
                     write!(state, " ")?;
                 }
             }
+            Token::HardCarriageReturn if state.in_table => {
+                state.end_header();
+                state.end_table_row(&lexed[i + 1..]);
+            }
+            // the line break ends the running head rather than a line of the body
+            Token::HardCarriageReturn if state.in_header => {
+                state.end_header();
+            }
             Token::HardCarriageReturn => {
                 state.had_carriage_return = true;
                 writeln!(state, "\n")?;
@@ -204,7 +511,12 @@ pub fn encode_page(
             Token::Sigil(s) => {
                 state.sigil = Some(s.data.clone());
             }
-            Token::Header => {}
+            Token::Header => {
+                if !state.in_header {
+                    state.out().header_start();
+                    state.in_header = true;
+                }
+            }
             Token::HypenAtEol => {
                 state.add_invisible_hyphen = true;
             }
@@ -214,8 +526,12 @@ pub fn encode_page(
             Token::UnderlineOff => {
                 state.current_style.underline = false;
             }
-            Token::GreekOn => {}
-            Token::GreekOff => {}
+            Token::GreekOn => {
+                state.current_style.greek = true;
+            }
+            Token::GreekOff => {
+                state.current_style.greek = false;
+            }
             Token::OneBlank => {
                 write!(state, " ")?;
             }
@@ -223,16 +539,28 @@ pub fn encode_page(
                 // not used
             }
             Token::VerticalLineOff => {}
-            Token::TD => {}
+            Token::TD => {
+                if !state.in_table {
+                    state.out().table_start();
+                    state.in_table = true;
+                }
+
+                let x = state.cell_x.take();
+                state.out().table_cell(x);
+            }
             Token::Null => {}
-            Token::PageLink { page_number, name } => {
-                if *page_number != 0 {
-                    state.encoder.pageref(*page_number);
+            Token::PageLink { page_number: target, name } => {
+                if *target != 0 {
+                    state.pageref(ctx, page_number, *target, links::label(&name.data));
                 } else {
                     // TODO image link
                 }
             }
-            Token::IDStart(_) => {}
+            Token::IDStart(_) | Token::NodeNumber2(_) | Token::WordAnchor => {
+                if let Some(id) = state.anchor_ids.id(t) {
+                    state.out().anchor(&id);
+                }
+            }
             Token::IDEnd(_) => {}
             Token::SubscriptOn => {
                 state.current_style.subscript = true;
@@ -259,7 +587,7 @@ pub fn encode_page(
             }
             Token::Copyright(_) => {}
             Token::AutoLink(page) => {
-                state.encoder.pageref(*page);
+                state.pageref(ctx, page_number, *page, None);
             }
             Token::SoftCarriageReturn => {
                 if !state.hyphen() {
@@ -279,18 +607,46 @@ pub fn encode_page(
                 write!(state, "\n")?;
             }
             Token::ListItemStart => {
-                panic!("actuall saw a list item");
+                // an item on its own still makes a list
+                if state.list_depth == 0 {
+                    state.out().list_start();
+                    state.list_depth += 1;
+                }
+
+                state.out().list_item();
             }
-            Token::ListItemEnd => {
+            Token::ListItemEnd => {}
+            Token::UnorderedListStart => {
+                state.out().list_start();
+                state.list_depth += 1;
+            }
+            Token::UnorderedListEnd => {
+                if state.list_depth > 0 {
+                    state.out().list_end();
+                    state.list_depth -= 1;
+                }
+            }
+            Token::SetX(x) if state.in_table => {
+                state.cell_x = Some(*x);
             }
-            Token::UnorderedListStart => {}
-            Token::UnorderedListEnd => {}
             Token::SetX(indent) => {
                 state.current_style.left_padding = NonZeroU16::new(*indent);
             }
-            Token::SV(_) => {}
-            Token::SVLemmaBegin(_) => {}
-            Token::SVLemmaStop => {}
+            Token::SV(id) => {
+                state.apparatus = Some(*id);
+            }
+            Token::SVLemmaBegin(lemma) => {
+                let target = state.apparatus.take().unwrap_or_default();
+
+                state.start_annotation(Annotation {
+                    kind: AnnotationKind::Lemma,
+                    target,
+                    lemma: Some(lemma.data.clone()),
+                });
+            }
+            Token::SVLemmaStop => {
+                state.end_annotation(AnnotationKind::Lemma);
+            }
             Token::CenteredOn => {
                 state.current_style.alignment = Some("center");
             }
@@ -305,7 +661,9 @@ pub fn encode_page(
             }
             Token::EOn => {}
             Token::EOff => {}
-            Token::BibIndex(_) => {}
+            Token::BibIndex(index) => {
+                state.out().bib_index(*index);
+            }
             Token::NotFirstLine => {}
             Token::Thumb => {}
             Token::EndNew(_) => {}
@@ -314,10 +672,10 @@ pub fn encode_page(
             }
             Token::UrlEnd => {
                 if let Some((content, url)) = state.queued_link.take() {
-                    state.encoder.link(&url, &content);
+                    let content = ctx.normalizer.apply(&content);
+                    state.out().link(&url, &content);
                 }
             }
-            Token::WordAnchor => {}
             Token::ThumbWWW => {}
             Token::S => {}
             Token::NoJustifyOn => {
@@ -328,35 +686,489 @@ pub fn encode_page(
             }
             Token::NextBlankFixed => {}
             Token::WordRest { space_at_end, data } => {
-                write!(state, "{}", data)?;
+                let style = &state.current_style;
+                let s = decoding::transliterate(data, style.greek, style.hebrew);
+                write!(state, "{}", s)?;
 
                 if *space_at_end {
                     write!(state, " ")?;
                 }
             }
             Token::WordIncomplete(word) => {
-                write!(state, "{}", word.data)?;
+                let style = &state.current_style;
+                let s = decoding::transliterate(&word.data, style.greek, style.hebrew);
+                write!(state, "{}", s)?;
                 state.word_incomplete = true;
             }
             Token::HyphenCK => {
                 state.add_hyphen_at_eol_separating_ck = true;
             }
-            Token::HebrewOn => {}
-            Token::HebrewOff => {}
-            Token::NodeNumber2(_) => {}
+            Token::HebrewOn => {
+                state.current_style.hebrew = true;
+            }
+            Token::HebrewOff => {
+                state.current_style.hebrew = false;
+            }
             Token::StrikeThroughOn => {
                 state.current_style.strikethrough = true;
             }
             Token::StrikeThroughOff => {
                 state.current_style.strikethrough = false;
             }
+            Token::SetY(_) if state.in_table => {
+                state.end_table_row(&lexed[i + 1..]);
+            }
             Token::SetY(_) => {}
-            Token::Cor(_) => {}
-            Token::EndCor => {}
+            Token::Cor(id) => {
+                state.start_annotation(Annotation {
+                    kind: AnnotationKind::Correction,
+                    target: *id as u64,
+                    lemma: None,
+                });
+            }
+            Token::EndCor => {
+                state.end_annotation(AnnotationKind::Correction);
+            }
             Token::DashedLine => {}
             Token::Unknown { raw, decoded } => {}
         }
     }
 
+    // the word is shown where it starts, but searched for on the page it ends on
+    let fragment = match state.pending_word.take() {
+        Some((word, style)) if state.hyphen() && state.queued_link.is_none() => {
+            let shown = ctx.normalizer.apply(&word).into_owned();
+            state.out().display(&shown, &style);
+            Some(word)
+        }
+        pending => {
+            state.pending_word = pending;
+            state.flush_word();
+            None
+        }
+    };
+
+    state.end_header();
+
+    // close annotations on this page and reopen them on the next
+    let annotations = std::mem::take(&mut state.annotations);
+    for annotation in annotations.iter().rev() {
+        state.out().annotation_end(annotation);
+    }
+
+    *carry = Carry {
+        separating_ck: fragment.is_some() && state.add_hyphen_at_eol_separating_ck,
+        fragment,
+        word_incomplete: state.word_incomplete,
+        annotations,
+    };
+
+    // every page has to stand on its own
+    if state.in_table {
+        state.out().table_end();
+    }
+
+    for _ in 0..state.list_depth {
+        state.out().list_end();
+    }
+
+    state.flush_text();
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{anchor::AnchorMap, for_flutter_encoder::ForFlutter, toc::Toc};
+
+    use super::*;
+
+    fn word(s: &str, space_at_end: bool) -> Token {
+        Token::Word {
+            space_at_end,
+            data: decoding::encode_vlado(s).unwrap(),
+        }
+    }
+
+    fn encode(pages: &[&[Token]]) -> Vec<ForFlutter> {
+        encode_with(&AnchorMap::default(), pages)
+    }
+
+    fn encode_with(anchors: &AnchorMap, pages: &[&[Token]]) -> Vec<ForFlutter> {
+        let toc = Toc {
+            entries: Toc::ingest([Ok("Werk".to_owned())], &[10]).unwrap(),
+        };
+        let (works, fonts, normalizer) = Default::default();
+        let links = Links { toc: &toc, anchors, page_count: 9 };
+        let ctx = Context {
+            works: &works,
+            fonts: &fonts,
+            normalizer: &normalizer,
+            links: &links,
+        };
+        let mut carry = Carry::default();
+
+        pages
+            .iter()
+            .enumerate()
+            .map(|(i, lexed)| {
+                let mut e = ForFlutter::new();
+                encode_page(&toc.entries[0], i + 1, lexed, &ctx, &mut carry, &mut e).unwrap();
+                e
+            })
+            .collect()
+    }
+
+    fn plain(pages: &[&[Token]]) -> Vec<String> {
+        encode(pages).into_iter().map(|e| e.plain).collect()
+    }
+
+    #[test]
+    fn normalization_across_words() {
+        // a combining mark set as a word of its own composes with the letter before it
+        let lexed = [word("Cafe", false), word("\u{301}", true), word("noir", false), Token::EndOfPage];
+        assert_eq!(plain(&[&lexed]), ["Caf\u{e9} noir"]);
+
+        let segments = encode(&[&lexed]).pop().unwrap().to_proto().segments;
+        let pieces = segments.iter().map(|s| s.pieces.len()).sum::<usize>();
+        assert_eq!(pieces, 1);
+    }
+
+    #[test]
+    fn hyphenation_within_page() {
+        let lexed = [
+            word("Hoff", false),
+            Token::HypenAtEol,
+            Token::SoftCarriageReturn,
+            word("nung", true),
+            word("Zuk", false),
+            Token::HyphenCK,
+            Token::SoftCarriageReturn,
+            word("ker", false),
+            Token::EndOfPage,
+        ];
+
+        assert_eq!(plain(&[&lexed]), ["Hoffnung Zucker"]);
+    }
+
+    #[test]
+    fn hyphenation_across_pages() {
+        let first = [word("Die", true), word("Hoff", false), Token::HypenAtEol, Token::EndOfPage];
+        let second = [word("nung", true), word("Bäk", false), Token::HyphenCK, Token::EndOfPage];
+        let third = [word("ker", true), word("backt", false), Token::EndOfPage];
+
+        assert_eq!(
+            plain(&[&first, &second, &third]),
+            ["Die ", "Hoffnung ", "Bäcker backt"]
+        );
+
+        // the start of the word is still shown on its own page
+        let segments = encode(&[&first, &second]).remove(0).to_proto().segments;
+        let shown = segments
+            .iter()
+            .flat_map(|s| &s.pieces)
+            .filter_map(|p| match &p.body {
+                Some(crate::for_flutter_proto::piece::Body::Chunk(c)) => Some(c.text.as_str()),
+                _ => None,
+            })
+            .collect::<String>();
+        assert_eq!(shown, "Die Hoff");
+    }
+
+    #[test]
+    fn lists() {
+        let lexed = [
+            word("Zutaten:", false),
+            Token::UnorderedListStart,
+            Token::ListItemStart,
+            word("Mehl", false),
+            Token::ListItemEnd,
+            Token::ListItemStart,
+            word("Zucker", false),
+            Token::ListItemEnd,
+            Token::UnorderedListEnd,
+            word("Dann", false),
+            Token::ListItemStart,
+            word("offen", false),
+            Token::EndOfPage,
+        ];
+
+        assert_eq!(plain(&[&lexed]), ["Zutaten: \nMehl\nZucker\nDann\noffen\n"]);
+
+        let segments = encode(&[&lexed]).pop().unwrap().to_proto().segments;
+        let items = segments
+            .iter()
+            .filter_map(|s| s.list.as_ref())
+            .map(|l| l.items.len())
+            .collect::<Vec<_>>();
+
+        assert_eq!(items, [2, 1]);
+    }
+
+    #[test]
+    fn tables() {
+        let lexed = [
+            word("Einwohner", false),
+            Token::HardCarriageReturn,
+            Token::TD,
+            word("Berlin", false),
+            Token::SetX(3000),
+            Token::TD,
+            word("3,4", false),
+            Token::HardCarriageReturn,
+            Token::TD,
+            word("Wien", false),
+            Token::SetY(200),
+            Token::TD,
+            word("Graz", false),
+            Token::TD,
+            word("0,3", false),
+            Token::HardCarriageReturn,
+            word("Quelle", false),
+            Token::EndOfPage,
+        ];
+
+        assert_eq!(table_columns(&lexed[2..]), 2);
+        assert_eq!(row_cells(&lexed[11..]), 2);
+
+        let e = encode(&[&lexed]).pop().unwrap();
+        assert_eq!(e.plain, "Einwohner\n\nBerlin\t3,4\nWien\nGraz\t0,3\nQuelle");
+
+        let segments = e.to_proto().segments;
+        let table = segments.iter().find_map(|s| s.table.as_ref()).unwrap();
+        let cells = table.rows.iter().map(|r| r.cells.len()).collect::<Vec<_>>();
+
+        assert_eq!(cells, [2, 1, 2]);
+        assert_eq!(table.rows[0].cells[1].x, 30.0);
+    }
+
+    #[test]
+    fn table_in_list() {
+        use crate::for_flutter_proto::piece::Body;
+
+        let lexed = [
+            word("Zutaten:", false),
+            Token::UnorderedListStart,
+            Token::ListItemStart,
+            word("Mehl", false),
+            Token::ListItemEnd,
+            Token::ListItemStart,
+            word("Eier", false),
+            Token::HardCarriageReturn,
+            Token::TD,
+            word("Huhn", false),
+            Token::TD,
+            word("2", false),
+            Token::ListItemStart,
+            word("Zucker", false),
+            Token::UnorderedListEnd,
+            word("Dann", false),
+            Token::EndOfPage,
+        ];
+
+        let segments = encode(&[&lexed]).pop().unwrap().to_proto().segments;
+        assert!(segments[0].pieces.iter().any(|p| matches!(&p.body, Some(Body::Chunk(c)) if c.text.contains("Zutaten"))));
+
+        // the table ends with the list, inside the item it started in
+        let list = segments.iter().find_map(|s| s.list.as_ref()).unwrap();
+        assert_eq!(list.items.len(), 2);
+
+        let table = list.items[1].segments.iter().find_map(|s| s.table.as_ref()).unwrap();
+        let cells = table.rows.iter().map(|r| r.cells.len()).collect::<Vec<_>>();
+        assert_eq!(cells, [2]);
+
+        // and a list in a table cell ends with the table, in its cell
+        let lexed = [
+            Token::TD,
+            word("Zutaten", false),
+            Token::TD,
+            Token::ListItemStart,
+            word("Mehl", false),
+            Token::ListItemStart,
+            word("Zucker", false),
+            Token::EndOfPage,
+        ];
+
+        let segments = encode(&[&lexed]).pop().unwrap().to_proto().segments;
+        let table = segments.iter().find_map(|s| s.table.as_ref()).unwrap();
+        let cells = &table.rows[0].cells;
+        assert_eq!(cells.len(), 2);
+
+        let list = cells[1].segments.iter().find_map(|s| s.list.as_ref()).unwrap();
+        assert_eq!(list.items.len(), 2);
+    }
+
+    #[test]
+    fn table_in_header() {
+        let lexed = [
+            Token::Header,
+            Token::TD,
+            word("Faust", false),
+            Token::TD,
+            word("12", false),
+            Token::HardCarriageReturn,
+            word("Habe", false),
+            Token::EndOfPage,
+        ];
+
+        let e = encode(&[&lexed]).pop().unwrap();
+        assert_eq!(e.header, "Faust\t12\n");
+        assert!(!e.plain.contains('\t'), "{:?}", e.plain);
+    }
+
+    #[test]
+    fn hebrew_direction() {
+        use crate::for_flutter_proto::{piece::Body, Direction};
+
+        let inline = [
+            word("Er", true),
+            word("sagte", true),
+            Token::HebrewOn,
+            word("\u{f9}\u{ec}\u{e5}\u{ed}", true),
+            Token::HebrewOff,
+            word("und", true),
+            word("ging", false),
+            Token::EndOfPage,
+        ];
+
+        let segments = encode(&[&inline]).pop().unwrap().to_proto().segments;
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].style.as_ref().unwrap().direction(), Direction::Ltr);
+        let langs = segments[0]
+            .pieces
+            .iter()
+            .filter_map(|p| match &p.body {
+                Some(Body::Chunk(c)) => Some(c.style.as_ref().unwrap().lang.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(langs, ["", "he", ""]);
+
+        let whole = [Token::HebrewOn, word("\u{f9}\u{ec}\u{e5}\u{ed}", false), Token::HebrewOff, Token::EndOfPage];
+        let segments = encode(&[&whole]).pop().unwrap().to_proto().segments;
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].style.as_ref().unwrap().direction(), Direction::Rtl);
+    }
+
+    #[test]
+    fn annotations() {
+        use crate::for_flutter_proto::{piece::Body, AnnotationKind};
+
+        let first = [
+            Token::Cor(7),
+            word("Fehler", true),
+            Token::EndCor,
+            Token::SV(12),
+            Token::SVLemmaBegin(crate::token::Name { data: "Lemma".to_owned() }),
+            word("über", true),
+            Token::EndOfPage,
+        ];
+        let second = [
+            word("Seiten", false),
+            Token::SVLemmaStop,
+            Token::BibIndex(3),
+            Token::BibIndex(3),
+            Token::EndOfPage,
+        ];
+
+        let encoded = encode(&[&first, &second]);
+        assert_eq!(encoded[1].bib_indices, [3]);
+
+        let annotations = encoded
+            .into_iter()
+            .map(|e| {
+                e.to_proto()
+                    .segments
+                    .into_iter()
+                    .flat_map(|s| s.pieces)
+                    .filter_map(|p| match p.body {
+                        Some(Body::Annotation(a)) => Some((a.kind(), a.target, a.end)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            annotations,
+            [
+                vec![
+                    (AnnotationKind::Correction, 7, false),
+                    (AnnotationKind::Correction, 7, true),
+                    (AnnotationKind::Lemma, 12, false),
+                    (AnnotationKind::Lemma, 12, true),
+                ],
+                vec![(AnnotationKind::Lemma, 12, false), (AnnotationKind::Lemma, 12, true)],
+            ]
+        );
+    }
+
+    #[test]
+    fn headers() {
+        use crate::for_flutter_proto::SegmentKind;
+
+        let lexed = [
+            Token::Header,
+            word("Faust", true),
+            word("I", false),
+            Token::HardCarriageReturn,
+            word("Habe", true),
+            word("nun", false),
+            Token::EndOfPage,
+        ];
+
+        let e = encode(&[&lexed]).pop().unwrap();
+        assert_eq!(e.header, "Faust I\n");
+        assert_eq!(e.plain.trim(), "Habe nun");
+
+        let kinds = e
+            .to_proto()
+            .segments
+            .iter()
+            .filter(|s| !s.pieces.is_empty())
+            .map(|s| s.kind())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, [SegmentKind::Header, SegmentKind::Body]);
+    }
+
+    #[test]
+    fn anchored_links() {
+        use crate::{for_flutter_proto::piece::Body, text::Page, token::Name};
+
+        let target = Page::from_tokens(2, &[word("Ziel", true), Token::NodeNumber2(9)]).unwrap();
+        let anchors = AnchorMap::collect([&target]);
+
+        let link = |name: &str| Token::PageLink {
+            page_number: 2,
+            name: Name { data: name.to_owned() },
+        };
+        let lexed = [
+            link("9"),
+            link("Seite 2"),
+            Token::AutoLink(2),
+            Token::AutoLink(20),
+            Token::EndOfPage,
+        ];
+
+        let refs = encode_with(&anchors, &[&lexed])
+            .pop()
+            .unwrap()
+            .to_proto()
+            .segments
+            .into_iter()
+            .flat_map(|s| s.pieces)
+            .filter_map(|p| match p.body {
+                Some(Body::PageRef(r)) => Some((r.r#ref, r.anchor, r.label, r.entry_title)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let target = |anchor: &str, label: &str| (2, anchor.to_owned(), label.to_owned(), "Werk".to_owned());
+
+        // the link to page 20 is broken, so it's left out
+        assert_eq!(
+            refs,
+            [target("node9", "9"), target("", "Seite 2"), target("", "")]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::ops::{Range, RangeInclusive};
+
+use eyre::eyre;
+use regex::Regex;
+
+use crate::toc::{Toc, TocItem};
+
+/// Restricts a conversion to part of a volume.
+///
+/// All given filters have to match for a page to be selected.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct Filter {
+    /// Only convert the TOC entry with this id or title path (e.g. `Werke/Faust`), including its children
+    #[clap(long)]
+    pub entry: Option<String>,
+
+    /// Only convert pages in this inclusive range (e.g. `100-200`, `100-` or `-200`)
+    #[clap(long, value_parser = parse_page_range)]
+    pub pages: Option<RangeInclusive<usize>>,
+
+    /// Only convert TOC entries whose title matches this regex, including their children
+    #[clap(long)]
+    pub title: Option<Regex>,
+}
+
+/// A run of pages belonging to a single TOC entry.
+#[derive(Debug)]
+pub struct Selection<'a> {
+    pub entry: &'a TocItem,
+    pub pages: Range<usize>,
+}
+
+impl Filter {
+    /// The selected pages of the volume, in page order.
+    pub fn select<'a>(&self, toc: &'a Toc) -> eyre::Result<Vec<Selection<'a>>> {
+        let roots = match &self.entry {
+            Some(entry) => vec![resolve_entry(toc, entry)?],
+            None => toc.entries.iter().collect(),
+        };
+
+        let mut selected = Vec::new();
+
+        for root in roots {
+            self.select_from(root, self.title.is_none(), &mut selected);
+        }
+
+        Ok(selected)
+    }
+
+    fn select_from<'a>(&self, item: &'a TocItem, matched: bool, out: &mut Vec<Selection<'a>>) {
+        let matched = matched
+            || self
+                .title
+                .as_ref()
+                .is_some_and(|title| title.is_match(&item.title));
+
+        if matched {
+            let mut pages = item.pages();
+
+            if let Some(range) = &self.pages {
+                pages.start = pages.start.max(*range.start());
+                pages.end = pages.end.min(range.end().saturating_add(1));
+            }
+
+            if !pages.is_empty() {
+                out.push(Selection { entry: item, pages });
+            }
+        }
+
+        for child in &item.children {
+            self.select_from(child, matched, out);
+        }
+    }
+}
+
+fn resolve_entry<'a>(toc: &'a Toc, entry: &str) -> eyre::Result<&'a TocItem> {
+    let found = match entry.parse::<usize>() {
+        Ok(id) => toc.get(id),
+        Err(_) => toc.find_path(entry.split('/')),
+    };
+
+    found.ok_or_else(|| eyre!("no TOC entry matches {:?}", entry))
+}
+
+fn parse_page_range(s: &str) -> eyre::Result<RangeInclusive<usize>> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+
+    let start = match start.trim() {
+        "" => 1,
+        n => n.parse()?,
+    };
+    let end = match end.trim() {
+        "" => usize::MAX,
+        n => n.parse()?,
+    };
+
+    if end < start {
+        return Err(eyre!("page range {} ends before it starts", s));
+    }
+
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toc() -> Toc {
+        let lines = [
+            "Werke",
+            " Faust",
+            "  Erster Teil",
+            "  Zweiter Teil",
+            " Gedichte",
+        ];
+        let page_numbers = [1, 3, 10, 12, 20];
+
+        Toc {
+            entries: Toc::ingest(lines.map(|l| Ok(l.to_owned())), &page_numbers).unwrap(),
+        }
+    }
+
+    fn selected(filter: Filter) -> Vec<(String, Range<usize>)> {
+        let toc = toc();
+
+        filter
+            .select(&toc)
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.entry.title.clone(), s.pages))
+            .collect()
+    }
+
+    fn sel(title: &str, pages: Range<usize>) -> (String, Range<usize>) {
+        (title.to_owned(), pages)
+    }
+
+    #[test]
+    fn no_filter_selects_everything() {
+        assert_eq!(
+            selected(Filter::default()),
+            [
+                sel("Faust", 1..3),
+                sel("Erster Teil", 3..10),
+                sel("Zweiter Teil", 10..12),
+                sel("Gedichte", 12..20),
+            ]
+        );
+    }
+
+    #[test]
+    fn entry_by_path_and_id() {
+        let by_path = Filter {
+            entry: Some("werke/faust".to_owned()),
+            ..Default::default()
+        };
+        let by_id = Filter {
+            entry: Some("1".to_owned()),
+            ..Default::default()
+        };
+
+        let expected = [
+            sel("Faust", 1..3),
+            sel("Erster Teil", 3..10),
+            sel("Zweiter Teil", 10..12),
+        ];
+
+        assert_eq!(selected(by_path), expected);
+        assert_eq!(selected(by_id), expected);
+
+        let missing = Filter {
+            entry: Some("Werke/Nichts".to_owned()),
+            ..Default::default()
+        };
+        assert!(missing.select(&toc()).is_err());
+    }
+
+    #[test]
+    fn page_range_and_title() {
+        let filter = Filter {
+            pages: Some(parse_page_range("5-15").unwrap()),
+            title: Some(Regex::new("Teil$").unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            selected(filter),
+            [sel("Erster Teil", 5..10), sel("Zweiter Teil", 10..12)]
+        );
+    }
+
+    #[test]
+    fn page_range_parsing() {
+        assert_eq!(parse_page_range("3-7").unwrap(), 3..=7);
+        assert_eq!(parse_page_range("3-").unwrap(), 3..=usize::MAX);
+        assert_eq!(parse_page_range("-7").unwrap(), 1..=7);
+        assert_eq!(parse_page_range("4").unwrap(), 4..=4);
+        assert!(parse_page_range("7-3").is_err());
+        assert!(parse_page_range("x").is_err());
+    }
+}
@@ -16,6 +16,8 @@ struct ChunkStyle {
     wide_spacing: bool,
     size: Option<NonZeroU8>,
     colour_gray: bool,
+    greek: bool,
+    hebrew: bool,
 }
 
 impl ChunkStyle {
@@ -30,6 +32,8 @@ impl ChunkStyle {
             wide_spacing,
             size,
             colour_gray,
+            greek,
+            hebrew,
         } = self;
 
         for_flutter_proto::ChunkStyle {
@@ -42,6 +46,13 @@ impl ChunkStyle {
             wide_spacing,
             colour_gray,
             size: size.map_or(1.0, |x| u8::from(x) as f32 / 100.0),
+            lang: if greek {
+                "el".to_owned()
+            } else if hebrew {
+                "he".to_owned()
+            } else {
+                String::new()
+            },
         }
     }
 }
@@ -54,7 +65,7 @@ struct SegmentStyle {
 }
 
 impl SegmentStyle {
-    fn to_proto(self) -> for_flutter_proto::SegmentStyle {
+    fn to_proto(self, rtl: bool) -> for_flutter_proto::SegmentStyle {
         let Self {
             left_padding,
             no_justification,
@@ -76,6 +87,12 @@ impl SegmentStyle {
         for_flutter_proto::SegmentStyle {
             left_padding: left_padding.map_or(0.0, |x| u16::from(x) as f32 / 100.0),
             alignment: alignment.into(),
+            direction: if rtl {
+                for_flutter_proto::Direction::Rtl
+            } else {
+                for_flutter_proto::Direction::Ltr
+            }
+            .into(),
         }
     }
 }
@@ -94,6 +111,8 @@ fn split_style(s: encoder::Style) -> (ChunkStyle, SegmentStyle) {
         color_gray,
         no_justification,
         alignment,
+        greek,
+        hebrew,
     } = s;
 
     let c = ChunkStyle {
@@ -106,6 +125,8 @@ fn split_style(s: encoder::Style) -> (ChunkStyle, SegmentStyle) {
         wide_spacing,
         size,
         colour_gray: color_gray,
+        greek,
+        hebrew,
     };
 
     let s = SegmentStyle {
@@ -121,10 +142,26 @@ fn split_style(s: encoder::Style) -> (ChunkStyle, SegmentStyle) {
 enum Piece {
     Chunk { style: ChunkStyle, text: String },
     Link { url: String, content: String },
-    PageRef(u32),
+    PageRef {
+        page: u32,
+        external: Option<String>,
+        anchor: Option<String>,
+        label: Option<String>,
+        entry: u32,
+        entry_title: String,
+    },
+    Anchor(String),
     SearchWord(String),
+    BibRef(u32),
+    Annotation {
+        kind: encoder::AnnotationKind,
+        target: u64,
+        lemma: Option<String>,
+        end: bool,
+    },
 }
 
+
 impl Piece {
     fn to_proto(self) -> for_flutter_proto::Piece {
         let body = match self {
@@ -136,28 +173,129 @@ impl Piece {
             Piece::Link { url, content: text } => {
                 for_flutter_proto::piece::Body::Link(for_flutter_proto::Link { url, text})
             },
-            Piece::PageRef(number) => {
-                for_flutter_proto::piece::Body::PageRef(for_flutter_proto::PageRef { r#ref: number })
+            Piece::PageRef { page, external, anchor, label, entry, entry_title } => {
+                for_flutter_proto::piece::Body::PageRef(for_flutter_proto::PageRef {
+                    r#ref: page,
+                    external: external.unwrap_or_default(),
+                    anchor: anchor.unwrap_or_default(),
+                    label: label.unwrap_or_default(),
+                    entry,
+                    entry_title,
+                })
+            },
+            Piece::Anchor(id) => {
+                for_flutter_proto::piece::Body::Anchor(for_flutter_proto::Anchor { id })
             },
             Piece::SearchWord(word) => {
                 for_flutter_proto::piece::Body::SearchWord(for_flutter_proto::SearchWord { word
                  })
             },
+            Piece::BibRef(index) => {
+                for_flutter_proto::piece::Body::BibRef(for_flutter_proto::BibRef { index })
+            },
+            Piece::Annotation { kind, target, lemma, end } => {
+                let kind = match kind {
+                    encoder::AnnotationKind::Correction => for_flutter_proto::AnnotationKind::Correction,
+                    encoder::AnnotationKind::Lemma => for_flutter_proto::AnnotationKind::Lemma,
+                };
+
+                for_flutter_proto::piece::Body::Annotation(for_flutter_proto::Annotation {
+                    kind: kind.into(),
+                    target,
+                    lemma: lemma.unwrap_or_default(),
+                    end,
+                })
+            },
         };
 
         for_flutter_proto::Piece { body: Some(body) }
     }
 }
 
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+struct List {
+    items: Vec<Vec<Segment>>,
+}
+
+impl List {
+    fn into_proto(self) -> for_flutter_proto::List {
+        for_flutter_proto::List {
+            items: self
+                .items
+                .into_iter()
+                .map(|segments| for_flutter_proto::ListItem {
+                    segments: segments.into_iter().map(|s| s.to_proto()).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+struct TableCell {
+    segments: Vec<Segment>,
+    x: Option<u16>,
+}
+
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+struct Table {
+    rows: Vec<Vec<TableCell>>,
+}
+
+impl Table {
+    fn into_proto(self) -> for_flutter_proto::Table {
+        let cell = |cell: TableCell| for_flutter_proto::TableCell {
+            segments: cell.segments.into_iter().map(|s| s.to_proto()).collect(),
+            x: cell.x.map_or(0.0, |x| x as f32 / 100.0),
+        };
+
+        for_flutter_proto::Table {
+            rows: self
+                .rows
+                .into_iter()
+                .map(|cells| for_flutter_proto::TableRow {
+                    cells: cells.into_iter().map(cell).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A table being built.
+struct OpenTable {
+    table: Table,
+    row: Vec<TableCell>,
+    /// The position of the cell being written to
+    x: Option<u16>,
+}
+
+/// A list or table being built. They can nest in each other's items and cells.
+enum OpenBlock {
+    List(List),
+    Table(OpenTable),
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct Segment {
     style: SegmentStyle,
     pieces: Vec<Piece>,
+    list: Option<List>,
+    table: Option<Table>,
+    /// Whether the segment is a running head rather than part of the body
+    #[serde(default)]
+    header: bool,
 }
 
 impl Segment {
     fn to_proto(self) -> for_flutter_proto::Segment {
-        for_flutter_proto::Segment { style: Some(self.style.to_proto()), pieces: self.pieces.into_iter().map(|p| p.to_proto()).collect() }
+        let kind = if self.header {
+            for_flutter_proto::SegmentKind::Header
+        } else {
+            for_flutter_proto::SegmentKind::Body
+        };
+        let rtl = self.is_rtl();
+
+        for_flutter_proto::Segment { style: Some(self.style.to_proto(rtl)), pieces: self.pieces.into_iter().map(|p| p.to_proto()).collect(), list: self.list.map(|l| l.into_proto()), table: self.table.map(|t| t.into_proto()), kind: kind.into() }
     }
 }
 
@@ -166,6 +304,9 @@ impl Segment {
         Self {
             style: Default::default(),
             pieces: Vec::new(),
+            list: None,
+            table: None,
+            header: false,
         }
     }
 
@@ -173,9 +314,46 @@ impl Segment {
         Self {
             style,
             pieces: vec![piece],
+            list: None,
+            table: None,
+            header: false,
+        }
+    }
+
+    fn new_list(list: List) -> Self {
+        Self {
+            list: Some(list),
+            ..Self::new()
+        }
+    }
+
+    fn new_table(table: Table) -> Self {
+        Self {
+            table: Some(table),
+            ..Self::new()
         }
     }
 
+    /// Hebrew words inside other text keep its direction, only a segment
+    /// that is all Hebrew reads right to left.
+    fn is_rtl(&self) -> bool {
+        let mut chunks = self.pieces.iter().filter_map(|p| match p {
+            Piece::Chunk { style, text } if !text.trim().is_empty() => Some(style),
+            _ => None,
+        });
+
+        chunks.next().is_some_and(|s| s.hebrew) && chunks.all(|s| s.hebrew)
+    }
+
+    /// Lists and tables are blocks that can't take pieces.
+    fn is_block(&self) -> bool {
+        self.list.is_some() || self.table.is_some()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pieces.is_empty() && !self.is_block()
+    }
+
     fn push_piece(&mut self, piece: Piece) {
         if let Piece::Chunk {
             style: new_style,
@@ -196,14 +374,29 @@ impl Segment {
 
 pub struct ForFlutter {
     pub plain: String,
+    /// The text of the page's running heads, kept out of `plain`
+    pub header: String,
+    /// The bibliography entries referenced on the page, in order of first reference
+    pub bib_indices: Vec<u32>,
+    /// The pages linked to, each with the position in `plain` the link is at
+    pub links: Vec<(u32, usize)>,
     segments: Vec<Segment>,
+    /// Lists and tables being built, innermost last, each with the segments
+    /// of the item or cell it is in
+    open_blocks: Vec<(Vec<Segment>, OpenBlock)>,
+    in_header: bool,
 }
 
 impl ForFlutter {
     pub fn new() -> Self {
         Self {
             plain: String::new(),
+            header: String::new(),
+            bib_indices: Vec::new(),
+            links: Vec::new(),
             segments: vec![Segment::new()],
+            open_blocks: Vec::new(),
+            in_header: false,
         }
     }
 
@@ -212,21 +405,124 @@ impl ForFlutter {
     }
 
     fn push_piece_samestyle(&mut self, piece: Piece) {
-        self.segments.last_mut().unwrap().push_piece(piece);
+        let last = self.segments.last_mut().unwrap();
+
+        if last.is_block() || last.header != self.in_header {
+            self.push_segment(Default::default(), piece);
+        } else {
+            last.push_piece(piece);
+        }
     }
 
     fn push_piece(&mut self, style: SegmentStyle, piece: Piece) {
-        if self.segments.last().unwrap().style == style {
+        let last = self.segments.last().unwrap();
+
+        if last.style == style && !last.is_block() && last.header == self.in_header {
             self.segments.last_mut().unwrap().push_piece(piece);
         } else {
-            self.segments.push(Segment::new_with_piece(style, piece));
+            self.push_segment(style, piece);
+        }
+    }
+
+    fn push_segment(&mut self, style: SegmentStyle, piece: Piece) {
+        self.segments.push(Segment {
+            header: self.in_header,
+            ..Segment::new_with_piece(style, piece)
+        });
+    }
+
+    /// End the blocks opened inside the innermost one that `is_target`, so it
+    /// is on top. Returns whether there is such a block.
+    fn close_blocks_inside(&mut self, is_target: impl Fn(&OpenBlock) -> bool) -> bool {
+        let Some(target) = self.open_blocks.iter().rposition(|(_, b)| is_target(b)) else {
+            return false;
+        };
+
+        while self.open_blocks.len() > target + 1 {
+            match self.open_blocks.last() {
+                Some((_, OpenBlock::List(_))) => self.list_end(),
+                Some((_, OpenBlock::Table(_))) => self.table_end(),
+                None => unreachable!(),
+            }
+        }
+
+        true
+    }
+
+    /// The text of a running head goes into `header` instead of `plain`.
+    fn plain_text(&mut self) -> &mut String {
+        if self.in_header {
+            &mut self.header
+        } else {
+            &mut self.plain
         }
     }
 }
 
 impl Encoder for ForFlutter {
     fn chunk(&mut self, s: &str, style: &crate::encoder::Style) {
-        self.plain.push_str(s);
+        self.plain_text().push_str(s);
+        self.display(s, style);
+    }
+
+    fn link(&mut self, url: &str, content: &str) {
+        self.push_piece_samestyle(Piece::Link {
+            url: url.to_owned(),
+            content: content.to_owned(),
+        });
+    }
+
+    fn pageref(&mut self, target: &encoder::PageRef) {
+        self.links.push((target.page, self.plain.len()));
+        self.push_piece_samestyle(Piece::PageRef {
+            page: target.page,
+            external: target.external.map(|e| e.to_owned()),
+            anchor: target.anchor.map(|a| a.to_owned()),
+            label: target.label.map(|l| l.to_owned()),
+            entry: target.entry.id as u32,
+            entry_title: target.entry.title.clone(),
+        });
+    }
+
+    fn anchor(&mut self, id: &str) {
+        self.push_piece_samestyle(Piece::Anchor(id.to_owned()));
+    }
+
+    fn searchword(&mut self, s: &str) {
+        self.push_piece_samestyle(Piece::SearchWord(s.to_owned()));
+    }
+
+    fn annotation_start(&mut self, annotation: &encoder::Annotation) {
+        self.push_piece_samestyle(Piece::Annotation {
+            kind: annotation.kind,
+            target: annotation.target,
+            lemma: annotation.lemma.clone(),
+            end: false,
+        });
+    }
+
+    fn annotation_end(&mut self, annotation: &encoder::Annotation) {
+        self.push_piece_samestyle(Piece::Annotation {
+            kind: annotation.kind,
+            target: annotation.target,
+            lemma: None,
+            end: true,
+        });
+    }
+
+    fn bib_index(&mut self, index: u32) {
+        if !self.bib_indices.contains(&index) {
+            self.bib_indices.push(index);
+        }
+
+        self.push_piece_samestyle(Piece::BibRef(index));
+    }
+
+    fn plain(&mut self, s: &str) {
+        self.plain_text().push_str(s);
+    }
+
+    fn display(&mut self, s: &str, style: &crate::encoder::Style) {
         let (chunk_style, segment_style) = split_style(style.clone());
         self.push_piece(
             segment_style,
@@ -237,18 +533,113 @@ impl Encoder for ForFlutter {
         );
     }
 
-    fn link(&mut self, url: &str, content: &str) {
-        self.push_piece_samestyle(Piece::Link {
-            url: url.to_owned(),
-            content: content.to_owned(),
-        });
+    fn header_start(&mut self) {
+        self.in_header = true;
     }
 
-    fn pageref(&mut self, page: u32) {
-        self.push_piece_samestyle(Piece::PageRef(page));
+    fn header_end(&mut self) {
+        self.in_header = false;
+        self.header.push('\n');
     }
 
-    fn searchword(&mut self, s: &str) {
-        self.push_piece_samestyle(Piece::SearchWord(s.to_owned()));
+    fn table_start(&mut self) {
+        let before = std::mem::replace(&mut self.segments, vec![Segment::new()]);
+
+        self.open_blocks.push((
+            before,
+            OpenBlock::Table(OpenTable {
+                table: Table::default(),
+                row: Vec::new(),
+                x: None,
+            }),
+        ));
+    }
+
+    fn table_cell(&mut self, x: Option<u16>) {
+        // a cell boundary inside a list in a cell stays in the list item
+        let Some((_, OpenBlock::Table(open))) = self.open_blocks.last_mut() else {
+            return;
+        };
+
+        let segments = std::mem::replace(&mut self.segments, vec![Segment::new()]);
+
+        if segments.iter().any(|s| !s.is_empty()) {
+            open.row.push(TableCell { segments, x: open.x });
+        }
+
+        let separate = !open.row.is_empty();
+        open.x = x;
+
+        if separate {
+            self.plain_text().push('\t');
+        }
+    }
+
+    fn table_row(&mut self) {
+        let Some((_, OpenBlock::Table(open))) = self.open_blocks.last_mut() else {
+            return;
+        };
+
+        let segments = std::mem::replace(&mut self.segments, vec![Segment::new()]);
+
+        if segments.iter().any(|s| !s.is_empty()) {
+            open.row.push(TableCell { segments, x: open.x.take() });
+        }
+
+        if !open.row.is_empty() {
+            open.table.rows.push(std::mem::take(&mut open.row));
+        }
+
+        self.plain_text().push('\n');
+    }
+
+    fn table_end(&mut self) {
+        if !self.close_blocks_inside(|b| matches!(b, OpenBlock::Table(_))) {
+            return;
+        }
+
+        self.table_row();
+
+        if let Some((before, OpenBlock::Table(open))) = self.open_blocks.pop() {
+            self.segments = before;
+            self.segments.push(Segment::new_table(open.table));
+        }
+    }
+
+    fn list_start(&mut self) {
+        let before = std::mem::replace(&mut self.segments, vec![Segment::new()]);
+        self.open_blocks.push((before, OpenBlock::List(List::default())));
+    }
+
+    fn list_item(&mut self) {
+        // an item boundary inside a table in an item stays in the table cell
+        let Some((_, OpenBlock::List(list))) = self.open_blocks.last_mut() else {
+            return;
+        };
+
+        let item = std::mem::replace(&mut self.segments, vec![Segment::new()]);
+
+        if item.iter().any(|s| !s.is_empty()) {
+            list.items.push(item);
+        }
+
+        self.plain_text().push('\n');
+    }
+
+    fn list_end(&mut self) {
+        if !self.close_blocks_inside(|b| matches!(b, OpenBlock::List(_))) {
+            return;
+        }
+
+        if let Some((before, OpenBlock::List(mut list))) = self.open_blocks.pop() {
+            let item = std::mem::replace(&mut self.segments, before);
+
+            if item.iter().any(|s| !s.is_empty()) {
+                list.items.push(item);
+            }
+
+            self.segments.push(Segment::new_list(list));
+            self.plain_text().push('\n');
+        }
     }
 }
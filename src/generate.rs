@@ -0,0 +1,348 @@
+use std::{fs::File, io::Cursor, path::Path};
+
+use eyre::{eyre, WrapErr};
+
+use crate::{
+    decoding,
+    text::{FormatVersion, Page, PageTable},
+    toc::Toc,
+    token::Token,
+};
+
+/// A volume described in JSON, for building test data without real Digibib
+/// files.
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "toc": [
+///     {
+///       "title": "Werke",
+///       "pages": [[{ "text": "Hallo Welt" }, "BoldOn", { "text": "fett" }]],
+///       "children": []
+///     }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct VolumeSpec {
+    /// Written to the magic header of `text.dki`, which is left out without one
+    #[serde(default)]
+    pub version: Option<i32>,
+    pub toc: Vec<EntrySpec>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct EntrySpec {
+    pub title: String,
+    #[serde(default)]
+    pub pages: Vec<Vec<PageItem>>,
+    #[serde(default)]
+    pub children: Vec<EntrySpec>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+pub enum PageItem {
+    /// Plain text, split into words at whitespace and into lines at newlines,
+    /// and encoded for the font the last `Font` token before it selects
+    Text { text: String },
+    Token(Token),
+}
+
+impl VolumeSpec {
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let file = File::open(path).wrap_err_with(|| format!("opening {}", path.display()))?;
+
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .wrap_err_with(|| format!("parsing {}", path.display()))
+    }
+
+    /// Write `tree.dki`, `tree.dka` and `text.dki` into the directory.
+    pub fn generate(&self, dir: &Path) -> eyre::Result<()> {
+        if self.toc.is_empty() {
+            return Err(eyre!("a volume needs at least one TOC entry"));
+        }
+
+        let mut lines = Vec::new();
+        let mut page_numbers = Vec::new();
+        let mut pages = Vec::new();
+
+        for entry in &self.toc {
+            entry.flatten(1, &mut lines, &mut page_numbers, &mut pages)?;
+        }
+
+        std::fs::create_dir_all(dir)?;
+
+        let tree_dki = encoding_rs::WINDOWS_1252.encode(&lines.concat()).0.into_owned();
+        std::fs::write(dir.join("tree.dki"), tree_dki)?;
+
+        let mut tree_dka = Cursor::new(Vec::new());
+        Toc::write_page_numbers(&mut tree_dka, &page_numbers)?;
+        std::fs::write(dir.join("tree.dka"), tree_dka.into_inner())?;
+
+        let mut text_dki = Cursor::new(Vec::new());
+        let format = match self.version {
+            Some(version) => FormatVersion::Versioned(version),
+            None => FormatVersion::Unversioned,
+        };
+        PageTable::write(&mut text_dki, &pages, format)?;
+        std::fs::write(dir.join("text.dki"), text_dki.into_inner())?;
+
+        Ok(())
+    }
+}
+
+impl EntrySpec {
+    fn flatten(
+        &self,
+        level: usize,
+        lines: &mut Vec<String>,
+        page_numbers: &mut Vec<i32>,
+        pages: &mut Vec<Page>,
+    ) -> eyre::Result<()> {
+        if self.title.trim().is_empty() {
+            return Err(eyre!("TOC entries need a title"));
+        }
+
+        if encoding_rs::WINDOWS_1252.encode(&self.title).2 {
+            return Err(eyre!("the title {:?} can't be encoded as Windows-1252", self.title));
+        }
+
+        lines.push(format!("{}{}\r\n", " ".repeat(level - 1), self.title.trim()));
+
+        for items in &self.pages {
+            let tokens = page_tokens(items)
+                .wrap_err_with(|| format!("in page {} of {:?}", pages.len() + 1, self.title))?;
+
+            pages.push(Page::from_tokens(pages.len() + 1, &tokens)?);
+        }
+
+        // the entry's own pages end where its first child's begin
+        page_numbers.push(i32::try_from(pages.len() + 1)?);
+
+        for child in &self.children {
+            child.flatten(level + 1, lines, page_numbers, pages)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn page_tokens(items: &[PageItem]) -> eyre::Result<Vec<Token>> {
+    let fonts = decoding::FontRegistry::default();
+    let mut font = 0;
+    let mut tokens = Vec::new();
+
+    for item in items {
+        match item {
+            PageItem::Text { text } => {
+                for (i, line) in text.lines().enumerate() {
+                    if i > 0 {
+                        tokens.push(Token::HardCarriageReturn);
+                    }
+
+                    let mut words = line.split_whitespace().peekable();
+
+                    while let Some(word) = words.next() {
+                        let data = fonts.table(font).encode(word)?;
+
+                        if data.len() > 0x7f {
+                            return Err(eyre!("{:?} is too long for a single word", word));
+                        }
+
+                        tokens.push(Token::Word {
+                            space_at_end: words.peek().is_some(),
+                            data,
+                        });
+                    }
+                }
+            }
+            PageItem::Token(token) => {
+                if let Token::Font(n) = token {
+                    font = *n;
+                }
+
+                tokens.push(token.clone());
+            }
+        }
+    }
+
+    if tokens.last() != Some(&Token::EndOfPage) {
+        tokens.push(Token::EndOfPage);
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        anchor::AnchorMap, decoding::FontRegistry, encoder, for_flutter_encoder::ForFlutter, links::Links,
+        normalize::Normalizer, split::WorkMap, text::Pages, typst,
+    };
+
+    use super::*;
+
+    const SPEC: &str = r#"{
+        "toc": [
+            {
+                "title": "Werke",
+                "pages": [[{ "text": "Vorwort" }]],
+                "children": [
+                    {
+                        "title": "Faust",
+                        "pages": [
+                            [{ "text": "Habe nun, ach!\nPhilosophie" }],
+                            ["BoldOn", { "text": "Juristerey" }, "BoldOff", { "Blanks": 2 },
+                             "UnorderedListStart", "ListItemStart", { "text": "Medizin" }, "ListItemEnd",
+                             "ListItemStart", { "text": "Theologie" }, "ListItemEnd", "UnorderedListEnd"]
+                        ]
+                    },
+                    {
+                        "title": "Gedichte",
+                        "pages": [
+                            [{ "text": "Über allen Gipfeln" }, "HardCarriageReturn",
+                             "TD", { "text": "ist" }, "TD", { "text": "Ruh" }, "HardCarriageReturn",
+                             "TD", { "text": "in" }, "HardCarriageReturn",
+                             { "text": "spürest du" }]
+                        ]
+                    }
+                ]
+            }
+        ]
+    }"#;
+
+    fn generated(version: Option<i32>) -> (tempfile::TempDir, VolumeSpec) {
+        let mut spec: VolumeSpec = serde_json::from_str(SPEC).unwrap();
+        spec.version = version;
+
+        let dir = tempfile::tempdir().unwrap();
+        spec.generate(dir.path()).unwrap();
+
+        (dir, spec)
+    }
+
+    #[test]
+    fn end_to_end() {
+        for version in [None, Some(1)] {
+            let (dir, _) = generated(version);
+
+            let toc = Toc::load(
+                File::open(dir.path().join("tree.dki")).unwrap(),
+                File::open(dir.path().join("tree.dka")).unwrap(),
+            )
+            .unwrap();
+
+            let faust = toc.find_path(["Werke", "Faust"]).unwrap();
+            assert_eq!(faust.pages(), 2..4);
+            assert_eq!(toc.find_path(["Werke", "Gedichte"]).unwrap().pages(), 4..5);
+
+            let text_dki = std::fs::read(dir.path().join("text.dki")).unwrap();
+            let mut text_dki = Cursor::new(text_dki.as_slice());
+            let page_table = PageTable::load(&mut text_dki, None).unwrap();
+            assert_eq!(
+                page_table.format(),
+                version.map_or(FormatVersion::Unversioned, FormatVersion::Versioned)
+            );
+            let pages = Pages::load(&mut text_dki, &page_table, 2, 2).unwrap();
+
+            let lexed = pages.pages[1].lex();
+            assert_eq!(lexed[0], Token::BoldOn);
+            assert_eq!(lexed.last(), Some(&Token::EndOfPage));
+
+            let works = WorkMap::default();
+            let fonts = FontRegistry::default();
+            let normalizer = Normalizer::default();
+            let anchors = AnchorMap::default();
+            let links = Links {
+                toc: &toc,
+                anchors: &anchors,
+                page_count: page_table.len(),
+            };
+            let ctx = encoder::Context {
+                works: &works,
+                fonts: &fonts,
+                normalizer: &normalizer,
+                links: &links,
+            };
+
+            let mut e = ForFlutter::new();
+            let mut carry = encoder::Carry::default();
+            encoder::encode_page(faust, 2, &pages.pages[0].lex(), &ctx, &mut carry, &mut e).unwrap();
+            assert!(e.plain.contains("Habe nun, ach!"));
+            assert!(e.plain.contains("Philosophie"));
+
+            let mut out = String::new();
+            typst::write_page(faust, 3, &lexed, &ctx, &mut Default::default(), &mut out).unwrap();
+            assert!(out.contains("Juristerey"));
+            assert!(out.contains("\n- Medizin\n- Theologie"));
+
+            let gedichte = toc.find_path(["Werke", "Gedichte"]).unwrap();
+            let lexed = Pages::load(&mut text_dki, &page_table, 4, 1).unwrap().pages[0].lex();
+            let mut out = String::new();
+            typst::write_page(gedichte, 4, &lexed, &ctx, &mut Default::default(), &mut out).unwrap();
+            assert!(out.contains("#table(columns: 2, stroke: none,\n[ist], [Ruh],\n[in], [],\n)\n"));
+        }
+    }
+
+    #[test]
+    fn magic_header() {
+        let (dir, _) = generated(Some(1));
+        let text_dki = std::fs::read(dir.path().join("text.dki")).unwrap();
+        assert_eq!(text_dki[..8], [0xcc, 0x24, 0x19, 0, 1, 0, 0, 0]);
+
+        let mut spec: VolumeSpec = serde_json::from_str(SPEC).unwrap();
+        spec.version = Some(7);
+        assert!(spec.generate(tempfile::tempdir().unwrap().path()).is_err());
+
+        let (dir, _) = generated(None);
+        let text_dki = std::fs::read(dir.path().join("text.dki")).unwrap();
+        assert_ne!(text_dki[..4], [0xcc, 0x24, 0x19, 0]);
+    }
+
+    #[test]
+    fn long_words() {
+        let spec = VolumeSpec {
+            version: None,
+            toc: vec![EntrySpec {
+                title: "Lang".to_owned(),
+                pages: vec![vec![PageItem::Text {
+                    text: "x".repeat(200),
+                }]],
+                children: Vec::new(),
+            }],
+        };
+
+        assert!(spec.generate(tempfile::tempdir().unwrap().path()).is_err());
+    }
+
+    #[test]
+    fn symbol_font() {
+        let items: Vec<PageItem> = serde_json::from_str(r#"[{ "Font": 2 }, { "text": "∑ α" }]"#).unwrap();
+        let tokens = page_tokens(&items).unwrap();
+
+        assert_eq!(tokens[1], Token::Word { space_at_end: true, data: vec![0xe5] });
+        assert_eq!(tokens[2], Token::Word { space_at_end: false, data: vec![0x61] });
+    }
+
+    #[test]
+    fn title_outside_1252() {
+        let mut spec: VolumeSpec = serde_json::from_str(SPEC).unwrap();
+        spec.toc[0].children[1].title = "Gedichte λ".into();
+
+        let err = spec.generate(tempfile::tempdir().unwrap().path()).unwrap_err();
+        assert!(err.to_string().contains("Gedichte λ"), "{}", err);
+    }
+
+    #[test]
+    fn empty_toc() {
+        let spec: VolumeSpec = serde_json::from_str(r#"{"toc": []}"#).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(spec.generate(dir.path()).is_err());
+        assert!(!dir.path().join("tree.dki").exists());
+
+        assert!(Toc::write_page_numbers(Cursor::new(Vec::new()), &[]).is_err());
+    }
+}
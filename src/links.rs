@@ -0,0 +1,193 @@
+use std::fmt;
+
+use crate::{
+    anchor::AnchorMap,
+    text::Page,
+    toc::{Toc, TocItem},
+    token::Token,
+};
+
+/// Looks up where links point, so links to pages outside the volume are
+/// caught instead of written out.
+pub struct Links<'a> {
+    pub toc: &'a Toc,
+    pub anchors: &'a AnchorMap,
+    /// The number of pages in `text.dki`, which are numbered from 1
+    pub page_count: usize,
+}
+
+/// The place a link resolved to.
+#[derive(Debug)]
+pub struct Target<'a> {
+    pub anchor: Option<&'a str>,
+    /// The TOC entry whose pages contain the target
+    pub entry: &'a TocItem,
+}
+
+/// A link to a page that doesn't exist.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// The page the link is on
+    pub source: usize,
+    pub target: u32,
+    pub label: Option<String>,
+}
+
+impl fmt::Display for BrokenLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "page {} links to page {}", self.source, self.target)?;
+
+        if let Some(label) = &self.label {
+            write!(f, " ({:?})", label)?;
+        }
+
+        write!(f, ", but the volume has no such page")
+    }
+}
+
+impl<'a> Links<'a> {
+    /// Resolve a link to the given page, optionally naming an anchor on it.
+    ///
+    /// Returns `None` if the page isn't part of the volume or no TOC entry
+    /// covers it.
+    pub fn resolve(&self, page: u32, name: Option<&str>) -> Option<Target<'a>> {
+        if page == 0 || page as usize > self.page_count {
+            return None;
+        }
+
+        let entry = self.toc.entry_for_page(page as usize)?;
+        let anchor = name
+            .and_then(|name| self.anchors.resolve(page, name))
+            .map(|a| a.id.as_str());
+
+        Some(Target { anchor, entry })
+    }
+
+    /// Every link in the pages that doesn't resolve.
+    pub fn check<'p>(&self, pages: impl IntoIterator<Item = &'p Page>) -> Vec<BrokenLink> {
+        let mut broken = Vec::new();
+
+        for page in pages {
+            for token in page.lex() {
+                let Some((target, label)) = link_target(&token) else { continue; };
+
+                if self.resolve(target, label).is_none() {
+                    broken.push(BrokenLink {
+                        source: page.number,
+                        target,
+                        label: label.map(|l| l.to_owned()),
+                    });
+                }
+            }
+        }
+
+        broken
+    }
+}
+
+/// The page a token links to and the link's label, if it's a link into the text.
+pub fn link_target(token: &Token) -> Option<(u32, Option<&str>)> {
+    match token {
+        // page 0 is an image link, which isn't a link into the text
+        Token::PageLink { page_number: 0, .. } => None,
+        Token::PageLink { page_number, name } => Some((*page_number, label(&name.data))),
+        Token::AutoLink(target) => Some((*target, None)),
+        _ => None,
+    }
+}
+
+/// The text around a position in a page's plain text, cut at whole words, to
+/// show where a link is.
+pub fn snippet(plain: &str, at: usize) -> String {
+    const CONTEXT: usize = 60;
+
+    let (before, after) = plain.split_at(at);
+
+    let mut start = before.char_indices().rev().nth(CONTEXT - 1).map_or(0, |(i, _)| i);
+    if start > 0 {
+        // don't start in the middle of a word
+        start = before[start..].find(char::is_whitespace).map_or(at, |i| start + i);
+    }
+
+    let mut end = after.char_indices().nth(CONTEXT).map_or(after.len(), |(i, _)| i);
+    if end < after.len() {
+        end = after[..end].rfind(char::is_whitespace).unwrap_or(0);
+    }
+
+    let text = [&before[start..], &after[..end]].concat();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The label of a `PageLink`, which is often left empty.
+pub fn label(name: &str) -> Option<&str> {
+    Some(name.trim()).filter(|n| !n.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::token::Name;
+
+    use super::*;
+
+    fn link(page_number: u32, name: &str) -> Token {
+        Token::PageLink {
+            page_number,
+            name: Name { data: name.to_owned() },
+        }
+    }
+
+    #[test]
+    fn resolve_and_check() {
+        let toc = Toc {
+            entries: Toc::ingest(
+                ["Werke", " Faust", " Gedichte"].map(|l| Ok(l.to_owned())),
+                &[2, 3, 4],
+            )
+            .unwrap(),
+        };
+
+        let first = Page::from_tokens(
+            1,
+            &[link(2, "Faust"), link(3, "4"), Token::AutoLink(7), link(0, "bild"), Token::EndOfPage],
+        )
+        .unwrap();
+        let second = Page::from_tokens(2, &[link(4, "Nirgends"), Token::EndOfPage]).unwrap();
+        let third = Page::from_tokens(3, &[Token::NodeNumber2(4), Token::EndOfPage]).unwrap();
+
+        let anchors = AnchorMap::collect([&first, &second, &third]);
+        let links = Links { toc: &toc, anchors: &anchors, page_count: 3 };
+
+        let target = links.resolve(3, Some("4")).unwrap();
+        assert_eq!(target.entry.title, "Gedichte");
+        assert_eq!(target.anchor, Some("node4"));
+
+        let target = links.resolve(2, Some("Faust")).unwrap();
+        assert_eq!(target.entry.title, "Faust");
+        assert_eq!(target.anchor, None);
+
+        assert!(links.resolve(0, None).is_none());
+        assert!(links.resolve(4, None).is_none());
+
+        assert_eq!(
+            links.check([&first, &second, &third]),
+            [
+                BrokenLink { source: 1, target: 7, label: None },
+                BrokenLink { source: 2, target: 4, label: Some("Nirgends".to_owned()) },
+            ]
+        );
+    }
+
+    #[test]
+    fn snippets() {
+        let plain = "Der Hund bellt,\nsiehe dort. Die Katze miaut.";
+        let at = plain.find("dort").unwrap() + 4;
+        assert_eq!(snippet(plain, at), "Der Hund bellt, siehe dort. Die Katze miaut.");
+
+        let long = "wort ".repeat(40);
+        let s = snippet(&long, 100);
+        assert!(s.len() <= 120);
+        assert!(s.split(' ').all(|w| w == "wort"));
+
+        assert_eq!(snippet("äöü", 2), "äöü");
+    }
+}
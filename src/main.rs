@@ -1,35 +1,146 @@
-use std::{fs::File, io::Cursor, path::PathBuf};
+use std::{collections::BTreeSet, ffi::OsStr, fs::File, io::{Cursor, Write}, path::{Path, PathBuf}};
 
 use binrw::BinReaderExt;
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
 use color_eyre::{Help, Result, SectionExt};
 use for_flutter_encoder::Segment;
 use ormlite::{sqlite::{SqliteConnectOptions, SqliteConnection}, ConnectOptions, Connection, Executor, Model};
 use prost::Message;
 use text::PageTable;
 use tikv_jemallocator::Jemalloc;
-use toc::TocItem;
 use tracing::*;
 
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
+mod anchor;
 mod decoding;
 mod encoder;
+mod filter;
 mod for_flutter_encoder;
+mod generate;
+mod links;
+mod metadata;
+mod normalize;
+mod split;
 mod text;
 mod toc;
+mod toc_export;
 mod token;
 mod typst;
 mod for_flutter_proto;
 
 #[derive(Parser)]
 struct Opts {
+    /// The volume's directory, needed by every subcommand but `backlinks`
     #[clap(short, long)]
-    data_dir: PathBuf,
+    data_dir: Option<PathBuf>,
 
+    /// Read a text.dki of an unsupported version with the layout of this
+    /// known version
+    #[clap(long)]
+    read_version_as: Option<i32>,
+
+    /// Convert into this database with the default options. This is how the
+    /// converter was run before it had subcommands, `convert` replaces it.
+    #[clap(short, long, hide = true)]
+    out_file: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert the volume into an SQLite database or Typst document
+    Convert(ConvertOpts),
+    /// Print the volume's table of contents
+    Toc {
+        #[clap(short, long, value_enum, default_value_t = toc_export::TocFormat::Text)]
+        format: toc_export::TocFormat,
+
+        /// Only print the entries containing this page
+        #[clap(long, conflicts_with = "search")]
+        page: Option<usize>,
+
+        /// Only print the entries whose titles match, best matches first
+        #[clap(long)]
+        search: Option<String>,
+    },
+    /// Check every page's atom and word counts against what lexing finds, and
+    /// that every link points to a page in the volume
+    Check,
+    /// Print the copyright and thumbnail markers of every page that has any, as JSON
+    Metadata,
+    /// List the pages linking to a page of a converted database
+    Backlinks {
+        /// The database written by `convert`
+        #[clap(long)]
+        database: PathBuf,
+        page: u32,
+    },
+    /// Write a synthetic volume described in a JSON file into the data directory
+    Generate {
+        #[clap(short, long)]
+        spec: PathBuf,
+    },
+}
+
+#[derive(clap::Args)]
+struct ConvertOpts {
+    /// The output file, or the output directory when splitting
     #[clap(short, long)]
     out_file: PathBuf,
+
+    #[clap(short, long, value_enum, default_value_t = Backend::Database)]
+    backend: Backend,
+
+    /// Write one output per TOC entry at this level (1 being the top level)
+    #[clap(long)]
+    split_level: Option<u8>,
+
+    /// Decode words in a font index with the given table (e.g. `4=zapf-dingbats`)
+    #[clap(long = "font-table", value_parser = decoding::parse_font_override)]
+    font_tables: Vec<(u8, decoding::FontTable)>,
+
+    /// Include running heads in the full-text index
+    #[clap(long)]
+    search_headers: bool,
+
+    #[clap(flatten)]
+    filter: filter::Filter,
+
+    #[clap(flatten)]
+    normalization: normalize::NormalizationOpts,
+}
+
+impl ConvertOpts {
+    /// The default options, writing to the given file.
+    fn with_out_file(out_file: PathBuf) -> Self {
+        #[derive(Parser)]
+        struct Defaults {
+            #[clap(flatten)]
+            opts: ConvertOpts,
+        }
+
+        let args = [OsStr::new("convert"), OsStr::new("--out-file"), out_file.as_os_str()];
+        Defaults::parse_from(args).opts
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Backend {
+    Database,
+    Typst,
+}
+
+impl Backend {
+    fn extension(self) -> &'static str {
+        match self {
+            Backend::Database => "sqlite",
+            Backend::Typst => "typ",
+        }
+    }
 }
 
 fn install_tracing() -> Result<()> {
@@ -39,6 +150,7 @@ fn install_tracing() -> Result<()> {
 
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
         .pretty();
     let filter_layer = tracing_subscriber::EnvFilter::builder()
         .with_default_directive(concat!(env!("CARGO_CRATE_NAME"), "=debug").parse()?)
@@ -58,6 +170,10 @@ pub struct Page {
     id: u32,
     content: Vec<u8>,
     plain: String,
+    header: String,
+    copyright: Option<u8>,
+    /// The page's thumbnail markers as JSON
+    thumbs: String,
 }
 
 #[tokio::main]
@@ -67,17 +183,272 @@ async fn main() -> Result<()> {
     color_eyre::install()?;
     install_tracing()?;
 
-    let tree_dki = File::open(opts.data_dir.join("tree.dki"))?;
-    let tree_dka = File::open(opts.data_dir.join("tree.dka"))?;
-    let text_dki = std::fs::read(opts.data_dir.join("text.dki"))?;
+    let command = match (opts.command, opts.out_file) {
+        (Some(command), None) => command,
+        (None, Some(out_file)) => Command::Convert(ConvertOpts::with_out_file(out_file)),
+        (Some(_), Some(_)) => {
+            return Err(color_eyre::eyre::eyre!(
+                "--out-file belongs to the subcommand, as in `convert --out-file`"
+            ))
+        }
+        (None, None) => Opts::command()
+            .error(clap::error::ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit(),
+    };
+
+    let data_dir = || {
+        opts.data_dir
+            .as_deref()
+            .ok_or_else(|| color_eyre::eyre::eyre!("this subcommand needs the volume's --data-dir"))
+    };
+
+    match command {
+        Command::Convert(convert_opts) => {
+            let toc = load_toc(data_dir()?)?;
+            convert(data_dir()?, opts.read_version_as, &toc, &convert_opts).await
+        }
+        Command::Toc { format, page, search } => {
+            let toc = load_toc(data_dir()?)?;
+            let out = std::io::stdout().lock();
+
+            match (page, search) {
+                (Some(page), _) => toc_export::write_page_path(&toc, page, out),
+                (None, Some(query)) => toc_export::write_search(&toc, &query, out),
+                (None, None) => toc_export::export(&toc, format, out),
+            }
+        }
+        Command::Check => {
+            let toc = load_toc(data_dir()?)?;
+            check(data_dir()?, opts.read_version_as, &toc)
+        }
+        Command::Metadata => metadata(data_dir()?, opts.read_version_as),
+        Command::Backlinks { database, page } => backlinks(&database, page).await,
+        Command::Generate { spec } => {
+            generate::VolumeSpec::load(&spec)?.generate(data_dir()?)
+        }
+    }
+}
+
+fn load_toc(data_dir: &Path) -> Result<toc::Toc> {
+    let tree_dki = File::open(data_dir.join("tree.dki"))?;
+    let tree_dka = File::open(data_dir.join("tree.dka"))?;
+
+    toc::Toc::load(tree_dki, tree_dka)
+}
+
+fn check(data_dir: &Path, read_version_as: Option<i32>, toc: &toc::Toc) -> Result<()> {
+    let text_dki = std::fs::read(data_dir.join("text.dki"))?;
+    let mut text_dki = Cursor::new(text_dki.as_slice());
+
+    let page_table = text::PageTable::load(&mut text_dki, read_version_as)?;
+    let pages = text::Pages::load(&mut text_dki, &page_table, 1, page_table.len())?;
+
+    let anchors = anchor::AnchorMap::collect(&pages.pages);
+    let links = links::Links {
+        toc,
+        anchors: &anchors,
+        page_count: page_table.len(),
+    };
+
+    let broken = links.check(&pages.pages);
+
+    for link in &broken {
+        println!("{}", link);
+    }
+
+    let mut mismatches = 0;
+
+    if page_table.format() == text::FormatVersion::Unversioned {
+        warn!("this text.dki layout has no atom and word counts to check");
+    } else {
+        for page in &pages.pages {
+            let lexed = page.lex_spanned();
+            let tokens = lexed.iter().map(|s| s.token.clone()).collect::<Vec<_>>();
+
+            if let Some(mismatch) = page.check_counts(page_table.format(), &tokens) {
+                println!("{}", mismatch);
+                mismatches += 1;
+
+                for spanned in lexed.iter().filter(|s| matches!(s.token, token::Token::Unknown { .. })) {
+                    let span = page.file_span(&spanned.span);
+                    println!("  unknown bytes at {:#x}..{:#x}", span.start, span.end);
+                }
+            }
+        }
+    }
+
+    if mismatches > 0 || !broken.is_empty() {
+        return Err(color_eyre::eyre::eyre!(
+            "{} of {} pages don't lex to their header counts, {} links are broken",
+            mismatches,
+            pages.pages.len(),
+            broken.len()
+        ));
+    }
+
+    Ok(())
+}
+
+fn metadata(data_dir: &Path, read_version_as: Option<i32>) -> Result<()> {
+    let text_dki = std::fs::read(data_dir.join("text.dki"))?;
+    let mut text_dki = Cursor::new(text_dki.as_slice());
+
+    let page_table = text::PageTable::load(&mut text_dki, read_version_as)?;
+    let pages = text::Pages::load(&mut text_dki, &page_table, 1, page_table.len())?;
+
+    let metadata = pages
+        .pages
+        .iter()
+        .map(|page| metadata::PageMetadata::collect(page.number, &page.lex()))
+        .filter(|m| !m.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut out = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &metadata)?;
+    writeln!(out)?;
+
+    Ok(())
+}
+
+async fn backlinks(database: &Path, page: u32) -> Result<()> {
+    let mut conn = SqliteConnectOptions::new()
+        .filename(database)
+        .read_only(true)
+        .connect()
+        .await?;
+
+    let rows: Vec<(u32, String)> = ormlite::query_as(
+        "SELECT source, snippet FROM backlink WHERE target = ? ORDER BY source",
+    )
+    .bind(page)
+    .fetch_all(&mut conn)
+    .await?;
+
+    for (source, snippet) in rows {
+        println!("{}\t{}", source, snippet);
+    }
+
+    Ok(())
+}
+
+/// Lex a page, warning if the result doesn't match its header counts.
+fn lex_checked(page: &text::Page, page_table: &PageTable) -> Vec<token::Token> {
+    let lexed = page.lex();
+
+    if let Some(mismatch) = page.check_counts(page_table.format(), &lexed) {
+        warn!("{}", mismatch);
+    }
+
+    lexed
+}
+
+async fn convert(
+    data_dir: &Path,
+    read_version_as: Option<i32>,
+    toc: &toc::Toc,
+    opts: &ConvertOpts,
+) -> Result<()> {
+    let selected = opts.filter.select(toc)?;
+
+    if selected.is_empty() {
+        warn!("the filters didn't select any pages");
+    }
+
+    let text_dki = std::fs::read(data_dir.join("text.dki"))?;
     let mut text_dki = Cursor::new(text_dki.as_slice());
 
-    let toc = toc::Toc::load(tree_dki, tree_dka)?;
-    let page_table = text::PageTable::load(&mut text_dki)?;
+    let page_table = text::PageTable::load(&mut text_dki, read_version_as)?;
+    debug!("text.dki format: {:?}", page_table.format());
+
+    let works = match opts.split_level {
+        Some(level) => split::split(toc, selected, level),
+        None => vec![split::Work {
+            title: toc.entries.first().map_or_else(String::new, |e| e.title.clone()),
+            name: String::new(),
+            selections: selected,
+        }],
+    };
+
+    // links can point anywhere in the volume, but only the anchors of pages
+    // that are written or linked to are needed
+    let mut anchors = anchor::AnchorMap::default();
+    let mut collected = BTreeSet::new();
+    let mut targets = BTreeSet::new();
+
+    for work in &works {
+        for selection in &work.selections {
+            let pages = text::Pages::load(&mut text_dki, &page_table, selection.pages.start, selection.pages.len())?;
+
+            for page in &pages.pages {
+                anchors.add(page);
+                collected.insert(page.number);
+                targets.extend(page.lex().iter().filter_map(links::link_target).map(|(p, _)| p as usize));
+            }
+        }
+    }
+
+    for number in targets.difference(&collected).filter(|p| (1..=page_table.len()).contains(*p)) {
+        let pages = text::Pages::load(&mut text_dki, &page_table, *number, 1)?;
+        anchors.add(&pages.pages[0]);
+    }
+
+    let links = links::Links {
+        toc,
+        anchors: &anchors,
+        page_count: page_table.len(),
+    };
+
+    let fonts = decoding::FontRegistry::with_overrides(&opts.font_tables);
+    let normalizer = normalize::Normalizer::new(&opts.normalization)?;
+    // without splitting there's one work, and the map just knows which pages were filtered out
+    let mut work_map = split::WorkMap::new(&works);
+
+    if opts.split_level.is_some() {
+        std::fs::create_dir_all(&opts.out_file)?;
+    }
+
+    for (i, work) in works.iter().enumerate() {
+        work_map.enter(i);
+
+        let out_file = if opts.split_level.is_some() {
+            opts.out_file
+                .join(&work.name)
+                .with_extension(opts.backend.extension())
+        } else {
+            opts.out_file.clone()
+        };
+
+        info!("writing {:?} to {}", work.title, out_file.display());
+
+        let ctx = encoder::Context {
+            works: &work_map,
+            fonts: &fonts,
+            normalizer: &normalizer,
+            links: &links,
+        };
+
+        match opts.backend {
+            Backend::Database => {
+                write_database(&mut text_dki, &page_table, work, &ctx, opts.search_headers, &out_file).await?
+            }
+            Backend::Typst => write_typst(&mut text_dki, &page_table, work, &ctx, &out_file)?,
+        }
+    }
 
+    Ok(())
+}
+
+async fn write_database(
+    text_dki: &mut Cursor<&[u8]>,
+    page_table: &PageTable,
+    work: &split::Work<'_>,
+    ctx: &encoder::Context<'_>,
+    search_headers: bool,
+    out_file: &Path,
+) -> Result<()> {
     let mut conn =
         SqliteConnectOptions::new()
-            .filename(&opts.out_file)
+            .filename(out_file)
             .journal_mode(ormlite::sqlite::SqliteJournalMode::Off)
             .synchronous(ormlite::sqlite::SqliteSynchronous::Off)
             .row_buffer_size(100000)
@@ -86,55 +457,158 @@ async fn main() -> Result<()> {
             .connect().await?;
 
 
-    ormlite::query(r#"
+    // running heads repeat on every page, so they only get in the way of searches
+    let indexed = if search_headers { "plain, header" } else { "plain" };
+    let new_indexed = if search_headers { "new.plain, new.header" } else { "new.plain" };
+
+    ormlite::query(&format!(r#"
 PRAGMA temp_store = MEMORY;
     
 CREATE TABLE page (
   id INTEGER not null primary key,
   content BLOB not null,
-  plain TEXT not null
+  plain TEXT not null,
+  header TEXT not null,
+  copyright INTEGER,
+  thumbs TEXT not null
 );
 
 CREATE VIRTUAL TABLE page_fts USING fts5(
-    plain,
+    {indexed},
     content='page',
     content_rowid='id'
 );
 
 CREATE TRIGGER page_ai AFTER INSERT ON page
     BEGIN
-        INSERT INTO page_fts (rowid, plain)
-        VALUES (new.id, new.plain);
+        INSERT INTO page_fts (rowid, {indexed})
+        VALUES (new.id, {new_indexed});
     END;
-   "#).execute(&mut conn).await?;
 
-    for page in &toc.entries {
-        do_page(&mut text_dki, &page_table, page, &mut conn).await?;
+CREATE TABLE anchor (
+  id TEXT not null,
+  page INTEGER not null,
+  offset INTEGER not null,
+  end INTEGER,
+  primary key (page, id)
+);
+
+CREATE TABLE backlink (
+  target INTEGER not null,
+  source INTEGER not null,
+  snippet TEXT not null
+);
+
+CREATE INDEX backlink_target ON backlink (target);
+
+CREATE TABLE bibliography (
+  bib_index INTEGER not null,
+  page INTEGER not null,
+  primary key (bib_index, page)
+);
+   "#)).execute(&mut conn).await?;
+
+    let mut carry = encoder::Carry::default();
+    let mut next_page = None;
+
+    for selection in &work.selections {
+        // words only run on into the next selection if it continues where this one ends
+        if next_page != Some(selection.pages.start) {
+            carry = Default::default();
+        }
+
+        do_pages(text_dki, page_table, selection, ctx, &mut carry, &mut conn).await?;
+        next_page = Some(selection.pages.end);
     }
 
     Ok(())
 }
 
-#[async_recursion::async_recursion]
-async fn do_page(mut f: &mut Cursor<&[u8]>, page_table: &PageTable, entry: &TocItem, conn: &mut SqliteConnection) -> Result<()> {
-    let pages = text::Pages::load(&mut f, page_table, entry.page_number, entry.page_count)?;
+async fn do_pages(mut f: &mut Cursor<&[u8]>, page_table: &PageTable, selection: &filter::Selection<'_>, ctx: &encoder::Context<'_>, carry: &mut encoder::Carry, conn: &mut SqliteConnection) -> Result<()> {
+    let entry = selection.entry;
+    let pages = text::Pages::load(&mut f, page_table, selection.pages.start, selection.pages.len())?;
 
     for (i, page) in pages.pages.iter().enumerate() {
-        let lexed = page.lex();
+        let lexed = lex_checked(page, page_table);
         let mut e = for_flutter_encoder::ForFlutter::new();
 
-        encoder::encode_page(entry, entry.page_number + i, &lexed, &mut e)?;
+        encoder::encode_page(entry, pages.start + i, &lexed, ctx, carry, &mut e)?;
+
+        let id = (pages.start + i) as u32;
+
+        for anchor in ctx.links.anchors.on_page(id) {
+            ormlite::query("INSERT INTO anchor (id, page, offset, end) VALUES (?, ?, ?, ?)")
+                .bind(&anchor.id)
+                .bind(anchor.page)
+                .bind(anchor.offset)
+                .bind(anchor.end)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        for index in &e.bib_indices {
+            ormlite::query("INSERT INTO bibliography (bib_index, page) VALUES (?, ?)")
+                .bind(index)
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        for (target, at) in &e.links {
+            ormlite::query("INSERT INTO backlink (target, source, snippet) VALUES (?, ?, ?)")
+                .bind(target)
+                .bind(id)
+                .bind(links::snippet(&e.plain, *at))
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        let metadata = metadata::PageMetadata::collect(id as usize, &lexed);
 
         Page {
-            id: (entry.page_number + i) as u32,
+            id,
             plain: e.plain.to_owned(),
+            header: e.header.to_owned(),
+            copyright: metadata.copyright,
+            thumbs: serde_json::to_string(&metadata.thumbs)?,
             content: e.to_proto().encode_to_vec(),
         }.insert(&mut *conn).await?;
     }
 
-    for child in &entry.children {
-        do_page(&mut f, page_table, child, conn).await?;
+    Ok(())
+}
+
+fn write_typst(
+    mut f: &mut Cursor<&[u8]>,
+    page_table: &PageTable,
+    work: &split::Work<'_>,
+    ctx: &encoder::Context<'_>,
+    out_file: &Path,
+) -> Result<()> {
+    let mut out = String::new();
+
+    typst::write_preamble(&work.title, &mut out)?;
+
+    let mut carry = encoder::Carry::default();
+    let mut next_page = None;
+
+    for selection in &work.selections {
+        if next_page != Some(selection.pages.start) {
+            carry = Default::default();
+        }
+
+        let pages = text::Pages::load(&mut f, page_table, selection.pages.start, selection.pages.len())?;
+
+        for (i, page) in pages.pages.iter().enumerate() {
+            let lexed = lex_checked(page, page_table);
+
+            typst::write_page(selection.entry, pages.start + i, &lexed, ctx, &mut carry, &mut out)?;
+        }
+
+        next_page = Some(selection.pages.end);
     }
 
+    std::fs::write(out_file, out)?;
+
     Ok(())
 }
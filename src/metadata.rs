@@ -0,0 +1,97 @@
+use crate::token::Token;
+
+/// Markers on a page that say something about the page rather than being
+/// part of its text.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct PageMetadata {
+    pub page: usize,
+    /// The class of copyright holder of the page's content, from its first
+    /// `Copyright` marker
+    pub copyright: Option<u8>,
+    pub thumbs: Vec<Thumb>,
+}
+
+/// A thumbnail marker.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct Thumb {
+    /// Whether the full image is on the web (`ThumbWWW`) rather than in the volume
+    pub www: bool,
+    /// The image following the marker, which is the thumbnail
+    pub image: Option<String>,
+}
+
+impl PageMetadata {
+    pub fn collect(page: usize, lexed: &[Token]) -> Self {
+        let mut metadata = Self {
+            page,
+            ..Default::default()
+        };
+
+        for (i, token) in lexed.iter().enumerate() {
+            match token {
+                Token::Copyright(class) => {
+                    metadata.copyright.get_or_insert(*class);
+                }
+                Token::Thumb | Token::ThumbWWW => {
+                    let image = lexed[i + 1..]
+                        .iter()
+                        .take_while(|t| !matches!(t, Token::Thumb | Token::ThumbWWW | Token::EndOfPage))
+                        .find_map(|t| match t {
+                            Token::Image { name, .. } | Token::InlineImage { name, .. } => {
+                                Some(name.data.clone())
+                            }
+                            _ => None,
+                        });
+
+                    metadata.thumbs.push(Thumb {
+                        www: matches!(token, Token::ThumbWWW),
+                        image,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        metadata
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.copyright.is_none() && self.thumbs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::token::Name;
+
+    use super::*;
+
+    #[test]
+    fn collect_metadata() {
+        let image = |name: &str| Token::Image {
+            width: 100,
+            name: Name { data: name.to_owned() },
+        };
+
+        let lexed = [
+            Token::Copyright(2),
+            Token::Thumb,
+            image("bild1.jpg"),
+            Token::ThumbWWW,
+            Token::Copyright(3),
+            Token::EndOfPage,
+        ];
+
+        let metadata = PageMetadata::collect(4, &lexed);
+        assert_eq!(metadata.copyright, Some(2));
+        assert_eq!(
+            metadata.thumbs,
+            [
+                Thumb { www: false, image: Some("bild1.jpg".to_owned()) },
+                Thumb { www: true, image: None },
+            ]
+        );
+
+        assert!(PageMetadata::collect(5, &[Token::EndOfPage]).is_empty());
+    }
+}
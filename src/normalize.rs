@@ -0,0 +1,201 @@
+use std::{borrow::Cow, collections::HashMap, path::PathBuf};
+
+use eyre::{eyre, WrapErr};
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum NormalizationForm {
+    None,
+    Nfc,
+    Nfkc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum SoftHyphens {
+    Keep,
+    Remove,
+}
+
+/// How decoded text is cleaned up before it's written out.
+#[derive(Clone, Debug, clap::Args)]
+pub struct NormalizationOpts {
+    /// Unicode normalization form applied to all text
+    #[clap(long, value_enum, default_value_t = NormalizationForm::Nfc)]
+    pub normalization: NormalizationForm,
+
+    /// What to do with soft hyphens (U+00AD)
+    #[clap(long, value_enum, default_value_t = SoftHyphens::Keep)]
+    pub soft_hyphens: SoftHyphens,
+
+    /// Expand typographic ligatures such as `ﬁ` (NFKC always does this)
+    #[clap(long)]
+    pub expand_ligatures: bool,
+
+    /// File of private use codepoints to replace, one `E0A1 replacement` per line
+    #[clap(long)]
+    pub pua_map: Option<PathBuf>,
+}
+
+pub struct Normalizer {
+    form: NormalizationForm,
+    soft_hyphens: SoftHyphens,
+    expand_ligatures: bool,
+    pua: HashMap<char, String>,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Self {
+            form: NormalizationForm::Nfc,
+            soft_hyphens: SoftHyphens::Keep,
+            expand_ligatures: false,
+            pua: HashMap::new(),
+        }
+    }
+}
+
+impl Normalizer {
+    pub fn new(opts: &NormalizationOpts) -> eyre::Result<Self> {
+        let pua = match &opts.pua_map {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .wrap_err_with(|| format!("reading {}", path.display()))?;
+                parse_pua_map(&content).wrap_err_with(|| format!("in {}", path.display()))?
+            }
+            None => HashMap::new(),
+        };
+
+        Ok(Self {
+            form: opts.normalization,
+            soft_hyphens: opts.soft_hyphens,
+            expand_ligatures: opts.expand_ligatures,
+            pua,
+        })
+    }
+
+    pub fn apply<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        let needs_work = self.form != NormalizationForm::None
+            || s.chars().any(|c| {
+                self.pua.contains_key(&c)
+                    || (self.soft_hyphens == SoftHyphens::Remove && c == '\u{ad}')
+                    || (self.expand_ligatures && ligature(c).is_some())
+            });
+
+        if !needs_work || s.is_ascii() {
+            return Cow::Borrowed(s);
+        }
+
+        let mut out = String::with_capacity(s.len());
+
+        for c in s.chars() {
+            if self.soft_hyphens == SoftHyphens::Remove && c == '\u{ad}' {
+                continue;
+            }
+
+            if let Some(replacement) = self.pua.get(&c) {
+                out.push_str(replacement);
+            } else if let Some(expanded) = ligature(c).filter(|_| self.expand_ligatures) {
+                out.push_str(expanded);
+            } else {
+                out.push(c);
+            }
+        }
+
+        match self.form {
+            NormalizationForm::None => Cow::Owned(out),
+            NormalizationForm::Nfc => Cow::Owned(out.nfc().collect()),
+            NormalizationForm::Nfkc => Cow::Owned(out.nfkc().collect()),
+        }
+    }
+}
+
+fn ligature(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ﬀ' => "ff",
+        'ﬁ' => "fi",
+        'ﬂ' => "fl",
+        'ﬃ' => "ffi",
+        'ﬄ' => "ffl",
+        'ﬅ' | 'ﬆ' => "st",
+        'Ĳ' => "IJ",
+        'ĳ' => "ij",
+        _ => return None,
+    })
+}
+
+fn is_private_use(c: char) -> bool {
+    matches!(c, '\u{e000}'..='\u{f8ff}' | '\u{f0000}'..='\u{ffffd}' | '\u{100000}'..='\u{10fffd}')
+}
+
+fn parse_pua_map(content: &str) -> eyre::Result<HashMap<char, String>> {
+    let mut map = HashMap::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (codepoint, replacement) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| eyre!("line {}: expected a codepoint and its replacement", i + 1))?;
+
+        let codepoint = codepoint.trim_start_matches("U+").trim_start_matches("u+");
+        let c = u32::from_str_radix(codepoint, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| eyre!("line {}: {:?} is not a codepoint", i + 1, codepoint))?;
+
+        // `apply` skips ASCII text, so only private use characters can be replaced
+        if !is_private_use(c) {
+            return Err(eyre!("line {}: U+{:04X} is not a private use codepoint", i + 1, c as u32));
+        }
+
+        map.insert(c, replacement.trim().to_owned());
+    }
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forms() {
+        let nfc = Normalizer::default();
+        let nfkc = Normalizer {
+            form: NormalizationForm::Nfkc,
+            ..Default::default()
+        };
+
+        assert_eq!(nfc.apply("e\u{301}ﬁ"), "éﬁ");
+        assert_eq!(nfkc.apply("e\u{301}ﬁ"), "éfi");
+        assert_eq!(nfc.apply("plain"), "plain");
+    }
+
+    #[test]
+    fn replacements() {
+        let n = Normalizer {
+            form: NormalizationForm::None,
+            soft_hyphens: SoftHyphens::Remove,
+            expand_ligatures: true,
+            pua: parse_pua_map("# comment\nE001 ꝛ\nU+E002 r\u{363}\n").unwrap(),
+        };
+
+        assert_eq!(n.apply("Hof\u{ad}fnung"), "Hoffnung");
+        assert_eq!(n.apply("ﬁnden"), "finden");
+        assert_eq!(n.apply("de\u{e001} \u{e002}"), "deꝛ r\u{363}");
+        assert_eq!(n.apply("\u{e003}"), "\u{e003}");
+    }
+
+    #[test]
+    fn bad_pua_map() {
+        assert!(parse_pua_map("E001").is_err());
+        assert!(parse_pua_map("ZZZZ x").is_err());
+        assert!(parse_pua_map("0041 B").is_err());
+        assert!(parse_pua_map("00E9 e").is_err());
+        assert!(parse_pua_map("F0001 x").is_ok());
+    }
+}
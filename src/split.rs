@@ -0,0 +1,230 @@
+use std::{collections::HashSet, ops::Range};
+
+use crate::{filter::Selection, toc::Toc};
+
+/// A group of selected pages that end up in the same output file.
+#[derive(Debug)]
+pub struct Work<'a> {
+    pub title: String,
+    pub name: String,
+    pub selections: Vec<Selection<'a>>,
+}
+
+/// Group the selected pages by their ancestor at the given level.
+///
+/// Pages of entries above that level form their own work.
+pub fn split<'a>(toc: &Toc, selected: Vec<Selection<'a>>, level: u8) -> Vec<Work<'a>> {
+    let mut works: Vec<(usize, Work<'a>)> = Vec::new();
+    let mut names = HashSet::new();
+
+    for selection in selected {
+        let path = toc.path(selection.entry.id);
+        let group = path
+            .get(level.saturating_sub(1) as usize)
+            .copied()
+            .unwrap_or(selection.entry);
+
+        match works.last_mut() {
+            Some((id, work)) if *id == group.id => work.selections.push(selection),
+            _ => {
+                let name = unique_name(&mut names, sanitize(&group.title));
+                works.push((
+                    group.id,
+                    Work {
+                        title: group.title.clone(),
+                        name,
+                        selections: vec![selection],
+                    },
+                ));
+            }
+        }
+    }
+
+    works.into_iter().map(|(_, w)| w).collect()
+}
+
+/// Turn a title into something usable as a file name.
+pub fn sanitize(title: &str) -> String {
+    let mut out = String::new();
+
+    for c in title.chars() {
+        if c.is_alphanumeric() {
+            out.push(c);
+        } else if !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+
+    // trimming the end after truncating, so the cut can't leave a separator there
+    let out = out.trim_start_matches('_').chars().take(80).collect::<String>();
+    let out = out.trim_end_matches('_');
+
+    if out.is_empty() {
+        "untitled".to_owned()
+    } else {
+        out.to_owned()
+    }
+}
+
+fn unique_name(names: &mut HashSet<String>, name: String) -> String {
+    let mut candidate = name.clone();
+    let mut n = 1;
+
+    while !names.insert(candidate.clone()) {
+        n += 1;
+        candidate = format!("{}-{}", name, n);
+    }
+
+    candidate
+}
+
+/// Where a page ends up in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location<'a> {
+    /// In the output being written
+    Current,
+    /// In another output file, by name
+    Work(&'a str),
+    /// Filtered out of every output
+    Excluded,
+}
+
+/// Knows which output file each selected page ends up in, so references to
+/// pages in other files can be marked as external.
+#[derive(Debug, Default)]
+pub struct WorkMap {
+    ranges: Vec<(Range<usize>, usize)>,
+    names: Vec<String>,
+    current: Option<usize>,
+}
+
+impl WorkMap {
+    pub fn new(works: &[Work]) -> Self {
+        let mut ranges = works
+            .iter()
+            .enumerate()
+            .flat_map(|(i, w)| w.selections.iter().map(move |s| (s.pages.clone(), i)))
+            .collect::<Vec<_>>();
+
+        ranges.sort_by_key(|(r, _)| r.start);
+
+        Self {
+            ranges,
+            names: works.iter().map(|w| w.name.clone()).collect(),
+            current: None,
+        }
+    }
+
+    pub fn enter(&mut self, work: usize) {
+        self.current = Some(work);
+    }
+
+    /// Where the page is written to. Before any work is entered, every page
+    /// counts as part of the current output.
+    pub fn locate(&self, page: u32) -> Location<'_> {
+        let Some(current) = self.current else { return Location::Current; };
+        let page = page as usize;
+
+        let idx = self.ranges.partition_point(|(r, _)| r.start <= page);
+
+        match idx.checked_sub(1).map(|i| &self.ranges[i]) {
+            Some((range, work)) if range.contains(&page) => {
+                if *work == current {
+                    Location::Current
+                } else {
+                    Location::Work(&self.names[*work])
+                }
+            }
+            _ => Location::Excluded,
+        }
+    }
+
+    /// The name of the output file the page is in, if that isn't the current one.
+    pub fn external(&self, page: u32) -> Option<&str> {
+        match self.locate(page) {
+            Location::Work(name) => Some(name),
+            Location::Current | Location::Excluded => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::Filter;
+
+    use super::*;
+
+    fn toc() -> Toc {
+        let lines = [
+            "Sammlung",
+            " Goethe",
+            "  Faust",
+            "  Gedichte",
+            " Schiller",
+            "  Gedichte",
+        ];
+        let page_numbers = [1, 2, 10, 12, 13, 20];
+
+        Toc {
+            entries: Toc::ingest(lines.map(|l| Ok(l.to_owned())), &page_numbers).unwrap(),
+        }
+    }
+
+    #[test]
+    fn split_by_level() {
+        let toc = toc();
+        let selected = Filter::default().select(&toc).unwrap();
+        let works = split(&toc, selected, 3);
+
+        let names = works.iter().map(|w| w.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Goethe", "Faust", "Gedichte", "Schiller", "Gedichte-2"]);
+
+        let selected = Filter::default().select(&toc).unwrap();
+        let works = split(&toc, selected, 2);
+
+        let names = works.iter().map(|w| w.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Goethe", "Schiller"]);
+        assert_eq!(works[0].selections.len(), 3);
+    }
+
+    #[test]
+    fn external_pages() {
+        let toc = toc();
+        let selected = Filter::default().select(&toc).unwrap();
+        let works = split(&toc, selected, 2);
+        let mut map = WorkMap::new(&works);
+
+        assert_eq!(map.external(15), None);
+
+        map.enter(0);
+        assert_eq!(map.external(5), None);
+        assert_eq!(map.external(15), Some("Schiller"));
+        assert_eq!(map.locate(5), Location::Current);
+        assert_eq!(map.locate(500), Location::Excluded);
+    }
+
+    #[test]
+    fn filtered_pages_are_excluded() {
+        let toc = toc();
+        let filter = Filter {
+            pages: Some(2..=9),
+            ..Default::default()
+        };
+        let works = split(&toc, filter.select(&toc).unwrap(), 1);
+        let mut map = WorkMap::new(&works);
+        map.enter(0);
+
+        assert_eq!(map.locate(2), Location::Current);
+        assert_eq!(map.locate(15), Location::Excluded);
+    }
+
+    #[test]
+    fn sanitized_names() {
+        assert_eq!(sanitize("Faust. Eine Tragödie"), "Faust_Eine_Tragödie");
+        assert_eq!(sanitize("  ../..  "), "untitled");
+
+        let long = format!("{} {}", "a".repeat(79), "b".repeat(10));
+        assert_eq!(sanitize(&long), "a".repeat(79));
+        assert_eq!(sanitize(&format!("  {}", "c".repeat(100))), "c".repeat(80));
+    }
+}
@@ -1,39 +1,180 @@
-use binrw::{BinRead, BinReaderExt, VecArgs};
+use binrw::{BinRead, BinReaderExt, BinWrite, BinWriterExt, VecArgs};
 use std::{
-    fmt::Debug,
+    fmt::{Debug, Display},
     io::{BufRead, BufReader, Read},
     iter::Peekable,
+    ops::Range,
 };
 
 use encoding_rs::WINDOWS_1252;
 use encoding_rs_io::DecodeReaderBytesBuilder;
 
+use eyre::eyre;
+use tracing::warn;
+
 use crate::token::Token;
 
-pub struct PageTable {
-    table: Vec<i32>,
-    has_magic: bool,
+const MAGIC: u32 = 0x1924cc;
+
+/// The versions of the versioned layout we know how to read, and what each
+/// one changes:
+///
+/// - 1: the magic and version make an 8 byte file header, and each page's
+///   size leaves out the size itself and is followed by the page's atom and
+///   word counts.
+///
+/// Other versions may lay out their headers differently, so they aren't read
+/// unless the caller says which known layout to use.
+pub const KNOWN_VERSIONS: &[i32] = &[1];
+
+/// The `text.dki` layouts found in Digibib releases.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatVersion {
+    /// No file header, and each page's size includes the two bytes of the
+    /// size itself.
+    Unversioned,
+    /// The file starts with the magic and a version number, and each page's
+    /// size is followed by its atom and word counts.
+    Versioned(i32),
 }
 
-impl PageTable {
-    pub fn load(mut text_dki: impl BinReaderExt) -> eyre::Result<Self> {
+impl FormatVersion {
+    /// Work out the layout from the start of the file, leaving the reader
+    /// at the page table.
+    ///
+    /// A version we don't know is an error, unless `read_as` names a known
+    /// version whose layout to read it with.
+    fn detect(mut text_dki: impl BinReaderExt, read_as: Option<i32>) -> eyre::Result<Self> {
         text_dki.seek(std::io::SeekFrom::Start(0))?;
-        let magic = text_dki.read_le::<u32>()?;
-        let has_magic = if magic == 0x1924cc {
-            let version = text_dki.read_le::<i32>()?;
-            true
-        } else {
+
+        if text_dki.read_le::<u32>()? != MAGIC {
             text_dki.seek(std::io::SeekFrom::Start(0))?;
-            false
-        };
+            return Ok(FormatVersion::Unversioned);
+        }
 
+        let version = text_dki.read_le::<i32>()?;
+
+        if KNOWN_VERSIONS.contains(&version) {
+            return Ok(FormatVersion::Versioned(version));
+        }
+
+        match read_as {
+            Some(known) => {
+                let format = FormatVersion::Versioned(known).supported()?;
+                warn!("reading text.dki version {} as version {}", version, known);
+                Ok(format)
+            }
+            None => Err(eyre!(
+                "unsupported text.dki version {} (known versions: {:?})",
+                version,
+                KNOWN_VERSIONS
+            )),
+        }
+    }
+
+    /// Fail for versions whose layout we don't know.
+    fn supported(self) -> eyre::Result<Self> {
+        match self {
+            FormatVersion::Versioned(version) if !KNOWN_VERSIONS.contains(&version) => {
+                Err(eyre!("unsupported text.dki version {}", version))
+            }
+            format => Ok(format),
+        }
+    }
+
+    // the layouts only differ between unversioned and version 1, the only
+    // known version, and `supported` keeps out the rest
+
+    fn file_header_size(self) -> usize {
+        match self {
+            FormatVersion::Unversioned => 0,
+            FormatVersion::Versioned(_) => 8,
+        }
+    }
+
+    fn page_header_size(self) -> usize {
+        match self {
+            FormatVersion::Unversioned => 2,
+            FormatVersion::Versioned(_) => 6,
+        }
+    }
+}
+
+pub struct PageTable {
+    table: Vec<i32>,
+    format: FormatVersion,
+}
+
+impl PageTable {
+    /// Read the file header and page table. `read_as` is a known version to
+    /// read an unsupported one as, see [`FormatVersion::detect`].
+    pub fn load(mut text_dki: impl BinReaderExt, read_as: Option<i32>) -> eyre::Result<Self> {
+        let format = FormatVersion::detect(&mut text_dki, read_as)?;
         let page_table = text_dki.read_le::<DkaBlock>()?.block;
 
         Ok(PageTable {
             table: page_table,
-            has_magic,
+            format,
         })
     }
+
+    pub fn format(&self) -> FormatVersion {
+        self.format
+    }
+
+    /// The number of pages in the volume.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Write pages in the given `text.dki` layout: the file header, the page
+    /// table, then the pages in order.
+    pub fn write(
+        mut text_dki: impl BinWriterExt,
+        pages: &[Page],
+        format: FormatVersion,
+    ) -> eyre::Result<()> {
+        if pages.is_empty() {
+            return Err(eyre!("text.dki needs at least one page"));
+        }
+
+        let format = format.supported()?;
+
+        let mut table = Vec::with_capacity(pages.len());
+        let mut address = format.file_header_size() + 4 + 4 * pages.len();
+
+        for page in pages {
+            table.push(i32::try_from(address)?);
+            address += format.page_header_size() + page.data.len();
+        }
+
+        match format {
+            FormatVersion::Unversioned => {}
+            FormatVersion::Versioned(version) => {
+                text_dki.write_le(&MAGIC)?;
+                text_dki.write_le(&version)?;
+            }
+        }
+
+        text_dki.write_le(&DkaBlock { block: table })?;
+
+        for page in pages {
+            let size = u16::try_from(page.data.len())?;
+
+            match format {
+                FormatVersion::Unversioned => text_dki.write_le(&(size + 2))?,
+                FormatVersion::Versioned(_) => {
+                    text_dki.write_le(&size)?;
+                    text_dki.write_le(&page.atom_count)?;
+                    text_dki.write_le(&page.word_count)?;
+                }
+            }
+
+            text_dki.write_le(&page.data)?;
+        }
+
+        Ok(())
+    }
 }
 
 pub struct Pages {
@@ -49,7 +190,7 @@ impl Pages {
         count: usize,
     ) -> eyre::Result<Self> {
         let pages = (page_number..(page_number + count))
-            .map(|i| Self::load_page(&mut text_dki, &page_table.table, i, page_table.has_magic))
+            .map(|i| Self::load_page(&mut text_dki, &page_table.table, i, page_table.format))
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(Pages {
@@ -62,25 +203,32 @@ impl Pages {
         mut text_dki: impl BinReaderExt,
         page_table: &[i32],
         page_number: usize,
-        has_magic: bool,
+        format: FormatVersion,
     ) -> eyre::Result<Page> {
         let address = page_table[page_number - 1];
         text_dki.seek(std::io::SeekFrom::Start(address as u64))?;
         let page_size = text_dki.read_le::<u16>()?;
-        let (atom_count, word_count, page_size) = if has_magic {
-            (
+        let (atom_count, word_count, page_size) = match format {
+            FormatVersion::Unversioned => {
+                let page_size = page_size.checked_sub(2).ok_or_else(|| {
+                    eyre!("page {} has invalid size {}", page_number, page_size)
+                })?;
+
+                (0, 0, page_size)
+            }
+            FormatVersion::Versioned(_) => (
                 text_dki.read_le::<u16>()?,
                 text_dki.read_le::<u16>()?,
                 page_size,
-            )
-        } else {
-            (0, 0, page_size - 2)
+            ),
         };
+        let offset = text_dki.stream_position()?;
         let data = text_dki
             .read_le_args::<Vec<u8>>(VecArgs::builder().count(page_size as usize).finalize())?;
 
         Ok(Page {
             number: page_number,
+            offset,
             atom_count,
             word_count,
             data,
@@ -91,48 +239,446 @@ impl Pages {
 #[derive(Debug)]
 pub struct Page {
     pub number: usize,
+    /// Where the page's data starts in `text.dki`
+    pub offset: u64,
     pub atom_count: u16,
     pub word_count: u16,
     pub data: Vec<u8>,
 }
 
 impl Page {
+    /// Lay out tokens the way `lex` reads them.
+    pub fn from_tokens(number: usize, tokens: &[Token]) -> eyre::Result<Self> {
+        let mut c = binrw::io::Cursor::new(Vec::new());
+
+        for token in tokens {
+            token.write(&mut c)?;
+        }
+
+        let word_count = tokens
+            .iter()
+            .filter(|t| matches!(t, Token::Word { .. }))
+            .count();
+
+        Ok(Page {
+            number,
+            offset: 0,
+            atom_count: u16::try_from(tokens.len())?,
+            word_count: u16::try_from(word_count)?,
+            data: c.into_inner(),
+        })
+    }
+
+    /// Compare the atom and word counts from the page header with the lexed
+    /// tokens. Layouts without counts always pass.
+    ///
+    /// What the header counts is an assumption. All we have to go on are the
+    /// field names of the original reader, so we take an atom to be a token
+    /// as `lex` reads it and a word to be a `Word` token, not counting
+    /// `WordRest` and `WordIncomplete`. This hasn't been checked against a real
+    /// `text.dki` with the header. The tests only use pages from `from_tokens`,
+    /// which counts the same way and so can't confirm it. If every page of a
+    /// real volume is off by a similar amount, the assumption is wrong rather
+    /// than the lexer.
+    pub fn check_counts(&self, format: FormatVersion, lexed: &[Token]) -> Option<CountMismatch> {
+        if format == FormatVersion::Unversioned {
+            return None;
+        }
+
+        let mismatch = CountMismatch {
+            page: self.number,
+            atoms: (self.atom_count, lexed.len()),
+            words: (
+                self.word_count,
+                lexed.iter().filter(|t| matches!(t, Token::Word { .. })).count(),
+            ),
+        };
+
+        let matches = |(expected, found): (u16, usize)| expected as usize == found;
+
+        if matches(mismatch.atoms) && matches(mismatch.words) {
+            None
+        } else {
+            Some(mismatch)
+        }
+    }
+
     pub fn lex(&self) -> Vec<Token> {
+        self.lex_spanned().into_iter().map(|s| s.token).collect()
+    }
+
+    /// Lex the page, keeping where each token's bytes are. Bytes that don't
+    /// form a token end up in `Token::Unknown`, so the spans cover the whole
+    /// page.
+    pub fn lex_spanned(&self) -> Vec<Spanned> {
         let mut c = binrw::io::Cursor::new(&self.data);
         let mut tokens = Vec::new();
         let mut unknown_buf = Vec::new();
 
+        let flush_unknown = |tokens: &mut Vec<Spanned>, unknown_buf: &mut Vec<u8>, end: usize| {
+            if !unknown_buf.is_empty() {
+                let raw = std::mem::take(unknown_buf);
+                let decoded = String::from_utf8_lossy(&raw).to_string();
+
+                tokens.push(Spanned {
+                    span: (end - raw.len())..end,
+                    token: Token::Unknown { raw, decoded },
+                });
+            }
+        };
+
         loop {
+            let start = c.position() as usize;
+
             match Token::read(&mut c) {
+                Ok(Token::Unknown { .. }) => {
+                    unknown_buf.extend(&self.data[start..c.position() as usize]);
+                }
                 Ok(t) => {
-                    if !unknown_buf.is_empty() {
-                        let decoded = String::from_utf8_lossy(&unknown_buf).to_string();
-                        let unk = Token::Unknown {
-                            raw: std::mem::replace(&mut unknown_buf, Vec::new()),
-                            decoded,
-                        };
-                        tokens.push(unk);
-                    }
-
-                    tokens.push(t)
+                    flush_unknown(&mut tokens, &mut unknown_buf, start);
+
+                    tokens.push(Spanned {
+                        token: t,
+                        span: start..c.position() as usize,
+                    })
+                }
+                Err(e) if e.is_eof() => {
+                    // a token cut off by the end of the page
+                    unknown_buf.extend(&self.data[start..]);
+                    flush_unknown(&mut tokens, &mut unknown_buf, self.data.len());
+
+                    return tokens;
                 }
-                Err(e) if e.is_eof() => return tokens,
                 Err(_) => {
+                    c.set_position(start as u64);
                     let op = c.read_le::<u8>().unwrap();
                     unknown_buf.push(op);
                 }
             }
         }
     }
+
+    /// Where a span of the page's data is in `text.dki`.
+    pub fn file_span(&self, span: &Range<usize>) -> Range<u64> {
+        (self.offset + span.start as u64)..(self.offset + span.end as u64)
+    }
+}
+
+/// A lexed token and the bytes it was read from, relative to the page's data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned {
+    pub token: Token,
+    pub span: Range<usize>,
+}
+
+/// A page where lexing didn't find what its header says is on it, usually
+/// because an opcode was misparsed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CountMismatch {
+    pub page: usize,
+    /// The atom count from the header and the number of lexed tokens
+    pub atoms: (u16, usize),
+    /// The word count from the header and the number of lexed words
+    pub words: (u16, usize),
+}
+
+impl Display for CountMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "page {}: header has {} atoms and {} words, lexing found {} and {}",
+            self.page, self.atoms.0, self.words.0, self.atoms.1, self.words.1
+        )
+    }
 }
 
-#[binrw::binread]
+#[binrw::binrw]
 #[derive(Debug)]
-#[br(little)]
+#[brw(little)]
 struct DkaBlock {
     #[br(temp, map = |x: u32| x + 1)]
+    #[bw(calc = block.len() as u32 - 1)]
     len: u32,
 
     #[br(count = len)]
     block: Vec<i32>,
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::token::Name;
+
+    fn name() -> impl Strategy<Value = Name> {
+        proptest::collection::vec(any::<u8>(), 0..16).prop_map(|bytes| Name {
+            data: encoding_rs::WINDOWS_1252
+                .decode_without_bom_handling(&bytes)
+                .0
+                .into_owned(),
+        })
+    }
+
+    fn token() -> impl Strategy<Value = Token> {
+        use Token::*;
+
+        let units = prop::sample::select(vec![
+            HardCarriageReturn,
+            EndOfPage,
+            ItalicsOn,
+            ItalicsOff,
+            BoldOn,
+            BoldOff,
+            Ly,
+            EndLink,
+            SuperScriptOn,
+            SuperScriptOff,
+            Header,
+            HypenAtEol,
+            UnderlineOn,
+            UnderlineOff,
+            GreekOn,
+            GreekOff,
+            OneBlank,
+            VerticalLineOn,
+            VerticalLineOff,
+            TD,
+            Null,
+            SubscriptOn,
+            SubscriptOff,
+            SoftCarriageReturn,
+            InvisibleHyphen,
+            LetterSpacingOn,
+            LetterSpacingOff,
+            HalfLineSpacing,
+            ListItemStart,
+            ListItemEnd,
+            UnorderedListStart,
+            UnorderedListEnd,
+            SVLemmaStop,
+            CenteredOn,
+            CenteredOff,
+            AlignRightOn,
+            AlignRightOff,
+            EOn,
+            EOff,
+            NotFirstLine,
+            Thumb,
+            UrlEnd,
+            WordAnchor,
+            ThumbWWW,
+            S,
+            NoJustifyOn,
+            NoJustifyOff,
+            NextBlankFixed,
+            HyphenCK,
+            HebrewOn,
+            HebrewOff,
+            StrikeThroughOn,
+            StrikeThroughOff,
+            EndCor,
+            DashedLine,
+        ]);
+
+        prop_oneof![
+            units,
+            any::<u8>().prop_map(Blanks),
+            (any::<bool>(), proptest::collection::vec(any::<u8>(), 0..128))
+                .prop_map(|(space_at_end, data)| Word { space_at_end, data }),
+            any::<u8>().prop_map(FontPreset),
+            (any::<u32>(), name()).prop_map(|(width, name)| Image { width, name }),
+            name().prop_map(ImageLink),
+            any::<u8>().prop_map(Font),
+            name().prop_map(FileName),
+            any::<u16>().prop_map(Concordance),
+            any::<u16>().prop_map(NodeNumber),
+            name().prop_map(Sigil),
+            (any::<u32>(), name()).prop_map(|(page_number, name)| PageLink { page_number, name }),
+            any::<u8>().prop_map(IDStart),
+            any::<u8>().prop_map(IDEnd),
+            any::<u8>().prop_map(Color),
+            (any::<u16>(), any::<u16>(), name())
+                .prop_map(|(width, height, name)| InlineImage { width, height, name }),
+            name().prop_map(SearchWord),
+            any::<u8>().prop_map(FontSize),
+            any::<u8>().prop_map(Copyright),
+            any::<u32>().prop_map(AutoLink),
+            any::<u16>().prop_map(SetX),
+            any::<u64>().prop_map(SV),
+            name().prop_map(SVLemmaBegin),
+            any::<u32>().prop_map(BibIndex),
+            any::<[u8; 3]>().prop_map(EndNew),
+            name().prop_map(UrlBegin),
+            (any::<bool>(), proptest::collection::vec(any::<u8>(), 0..128)).prop_map(|(space_at_end, bytes)| WordRest {
+                space_at_end,
+                data: encoding_rs::WINDOWS_1252
+                    .decode_without_bom_handling(&bytes)
+                    .0
+                    .into_owned(),
+            }),
+            name().prop_map(WordIncomplete),
+            any::<u32>().prop_map(NodeNumber2),
+            any::<u16>().prop_map(SetY),
+            any::<u32>().prop_map(Cor),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn lex_write_lex(tokens in proptest::collection::vec(token(), 0..64)) {
+            let page = Page::from_tokens(1, &tokens).unwrap();
+            prop_assert_eq!(page.lex(), tokens);
+        }
+
+        #[test]
+        fn bytes_lex_write_lex(data in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let page = Page { number: 1, offset: 0, atom_count: 0, word_count: 0, data };
+            let lexed = page.lex();
+
+            let written = Page::from_tokens(1, &lexed).unwrap();
+            prop_assert_eq!(&written.data, &page.data);
+            prop_assert_eq!(written.lex(), lexed);
+        }
+
+        #[test]
+        fn spans_cover_page(data in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let page = Page { number: 1, offset: 0, atom_count: 0, word_count: 0, data };
+            let mut end = 0;
+
+            for spanned in page.lex_spanned() {
+                prop_assert_eq!(spanned.span.start, end);
+                prop_assert!(spanned.span.end > spanned.span.start);
+                end = spanned.span.end;
+            }
+
+            prop_assert_eq!(end, page.data.len());
+        }
+
+        #[test]
+        fn text_dki_round_trip(
+            pages in proptest::collection::vec(proptest::collection::vec(token(), 0..16), 1..8),
+            format in prop_oneof![
+                Just(FormatVersion::Unversioned),
+                proptest::sample::select(KNOWN_VERSIONS).prop_map(FormatVersion::Versioned),
+            ],
+        ) {
+            let pages = pages
+                .iter()
+                .enumerate()
+                .map(|(i, tokens)| Page::from_tokens(i + 1, tokens).unwrap())
+                .collect::<Vec<_>>();
+
+            let mut text_dki = binrw::io::Cursor::new(Vec::new());
+            PageTable::write(&mut text_dki, &pages, format).unwrap();
+
+            let page_table = PageTable::load(&mut text_dki, None).unwrap();
+            prop_assert_eq!(page_table.format(), format);
+            let loaded = Pages::load(&mut text_dki, &page_table, 1, pages.len()).unwrap();
+
+            for (original, loaded) in pages.iter().zip(&loaded.pages) {
+                prop_assert_eq!(&original.data, &loaded.data);
+                prop_assert_eq!(original.lex(), loaded.lex());
+
+                if format != FormatVersion::Unversioned {
+                    prop_assert_eq!(original.atom_count, loaded.atom_count);
+                    prop_assert_eq!(original.word_count, loaded.word_count);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_version() {
+        let page = Page::from_tokens(1, &[Token::BoldOn, Token::EndOfPage]).unwrap();
+        let mut text_dki = binrw::io::Cursor::new(Vec::new());
+        PageTable::write(&mut text_dki, &[page], FormatVersion::Versioned(1)).unwrap();
+
+        // the same file, claiming another version
+        let with_version = |version: i32| {
+            let mut data = text_dki.get_ref().clone();
+            data[4..8].copy_from_slice(&version.to_le_bytes());
+            binrw::io::Cursor::new(data)
+        };
+
+        for version in [-3, 0, 2] {
+            let err = PageTable::load(with_version(version), None).err().unwrap();
+            assert!(
+                err.to_string().contains(&format!("unsupported text.dki version {}", version)),
+                "{}",
+                err
+            );
+        }
+
+        let mut data = with_version(2);
+        let page_table = PageTable::load(&mut data, Some(1)).unwrap();
+        assert_eq!(page_table.format(), FormatVersion::Versioned(1));
+        let pages = Pages::load(&mut data, &page_table, 1, 1).unwrap();
+        assert_eq!(pages.pages[0].lex(), [Token::BoldOn, Token::EndOfPage]);
+
+        assert!(PageTable::load(with_version(2), Some(3)).is_err());
+
+        let page = Page::from_tokens(1, &[Token::EndOfPage]).unwrap();
+        let mut out = binrw::io::Cursor::new(Vec::new());
+        assert!(PageTable::write(&mut out, &[page], FormatVersion::Versioned(0)).is_err());
+    }
+    #[test]
+    fn count_check() {
+        let tokens = [
+            Token::Word { space_at_end: true, data: b"zwei".to_vec() },
+            Token::Word { space_at_end: false, data: b"Worte".to_vec() },
+            Token::EndOfPage,
+        ];
+        let mut page = Page::from_tokens(4, &tokens).unwrap();
+        let lexed = page.lex();
+        let format = FormatVersion::Versioned(1);
+
+        assert_eq!(page.check_counts(format, &lexed), None);
+
+        page.atom_count = 2;
+        assert_eq!(
+            page.check_counts(format, &lexed),
+            Some(CountMismatch { page: 4, atoms: (2, 3), words: (2, 2) })
+        );
+        assert_eq!(page.check_counts(FormatVersion::Unversioned, &lexed), None);
+    }
+    #[test]
+    fn unwritable_tokens() {
+        let word = |len: usize| Token::Word { space_at_end: true, data: vec![b'a'; len] };
+        let name = |data: &str| Token::SearchWord(Name { data: data.to_owned() });
+
+        assert!(Page::from_tokens(1, &[word(127)]).is_ok());
+        assert!(Page::from_tokens(1, &[word(128)]).is_err());
+        assert!(Page::from_tokens(1, &[name(&"a".repeat(255))]).is_ok());
+        assert!(Page::from_tokens(1, &[name(&"a".repeat(256))]).is_err());
+        assert!(Page::from_tokens(1, &[name("Preis: 5 €")]).is_ok());
+        assert!(Page::from_tokens(1, &[name("λόγος")]).is_err());
+
+        let word_rest = |len: usize| Token::WordRest { space_at_end: false, data: "a".repeat(len) };
+        assert!(Page::from_tokens(1, &[word_rest(127)]).is_ok());
+        assert!(Page::from_tokens(1, &[word_rest(128)]).is_err());
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = [Token::BoldOn, Token::Blanks(3), Token::EndOfPage];
+        let mut page = Page::from_tokens(1, &tokens).unwrap();
+        // a word whose length runs past the end of the page
+        page.data.extend([1, 5, b'a']);
+        page.offset = 100;
+
+        let spanned = page.lex_spanned();
+        let spans = spanned.iter().map(|s| s.span.clone()).collect::<Vec<_>>();
+
+        assert_eq!(spans[..3], [0..1, 1..3, 3..4]);
+        assert!(matches!(&spanned[3].token, Token::Unknown { raw, .. } if raw == &[1]));
+        assert_eq!(spans.last(), Some(&(6..7)));
+        assert_eq!(page.file_span(&spanned[1].span), 101..103);
+
+        // 0xff isn't an opcode, so it's kept like any other unknown byte
+        let page = Page { number: 1, offset: 0, atom_count: 0, word_count: 0, data: vec![0xfe, 0xff, 2] };
+        let lexed = page.lex();
+        assert_eq!(lexed[0], Token::Unknown { raw: vec![0xfe, 0xff], decoded: "\u{fffd}\u{fffd}".to_owned() });
+        assert_eq!(Page::from_tokens(1, &lexed).unwrap().data, page.data);
+    }
+}
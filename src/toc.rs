@@ -1,11 +1,15 @@
-use binrw::BinReaderExt;
+use binrw::{BinReaderExt, BinWriterExt};
 use std::{
+    collections::VecDeque,
     io::{BufRead, BufReader, Read},
     iter::Peekable,
+    ops::Range,
 };
 
 use encoding_rs::WINDOWS_1252;
 use encoding_rs_io::DecodeReaderBytesBuilder;
+use eyre::eyre;
+use tracing::warn;
 
 pub struct Toc {
     pub entries: Vec<TocItem>,
@@ -17,48 +21,147 @@ impl Toc {
             .encoding(Some(WINDOWS_1252))
             .build(tree_dki);
 
-        let lines = BufReader::new(tree_dki)
-            .lines()
-            .collect::<Result<Vec<_>, _>>()?;
-
         let page_numbers = Self::load_page_numbers(&mut tree_dka)?;
 
-        assert_eq!(lines.len(), page_numbers.block.len());
-
-        let toc = Self::ingest(lines, page_numbers.block);
+        let toc = Self::ingest(BufReader::new(tree_dki).lines(), &page_numbers.block)?;
 
         Ok(Toc { entries: toc })
     }
 
+    /// Write a `tree.dka` holding the given page numbers, one per entry, each
+    /// being the page after the entry's own pages.
+    pub fn write_page_numbers(mut dka: impl BinWriterExt, page_numbers: &[i32]) -> eyre::Result<()> {
+        // the block stores its length minus one, so it can't be empty
+        if page_numbers.is_empty() {
+            return Err(eyre!("a TOC needs at least one entry"));
+        }
+
+        // we don't know what the first three blocks mean, nothing reads them
+        for _ in 0..3 {
+            dka.write_le(&DkaBlock { block: vec![0] })?;
+        }
+
+        dka.write_le(&DkaBlock {
+            block: page_numbers.to_vec(),
+        })?;
+
+        Ok(())
+    }
+
     fn load_page_numbers<R: BinReaderExt>(dka: &mut R) -> eyre::Result<DkaBlock> {
-        dbg!(dka.read_le::<DkaBlock>()?.len);
-        dbg!(dka.read_le::<DkaBlock>()?.len);
-        dbg!(dka.read_le::<DkaBlock>()?.len);
+        // the first three blocks don't hold anything we need
+        for _ in 0..3 {
+            dka.read_le::<DkaBlock>()?;
+        }
         Ok(dka.read_le::<DkaBlock>()?)
     }
 
-    fn ingest(lines: Vec<String>, page_numbers: Vec<i32>) -> Vec<TocItem> {
-        let it = lines.into_iter().enumerate().map(|(i, line)| {
-            let trimmed = line.trim_start();
-            let level = (line.len() - trimmed.len()) + 1;
-            let page_number = if i == 0 { 1 } else { page_numbers[i - 1] };
-            TocItem {
-                id: i,
-                title: trimmed.to_owned(),
-                level: level as u8,
-                page_number: page_number as usize,
-                page_count: (page_numbers[i] - page_number) as usize,
-                children: Vec::new(),
+    pub fn ingest(
+        lines: impl IntoIterator<Item = std::io::Result<String>>,
+        page_numbers: &[i32],
+    ) -> eyre::Result<Vec<TocItem>> {
+        let mut items = Vec::new();
+        // blank lines are only allowed at the end of the file, so we hold on to
+        // them until we know whether anything follows
+        let mut pending_blank = None;
+        let mut line_count = 0;
+
+        for (i, line) in lines.into_iter().enumerate() {
+            let line = line?;
+            let line_number = i + 1;
+            line_count = line_number;
+
+            if line.trim().is_empty() {
+                pending_blank.get_or_insert(line_number);
+                continue;
+            }
+
+            if let Some(blank) = pending_blank {
+                return Err(eyre!("tree.dki line {}: empty TOC entry", blank));
+            }
+
+            if i >= page_numbers.len() {
+                warn!(
+                    "tree.dki has more entries than tree.dka has page numbers ({}), ignoring from line {}",
+                    page_numbers.len(),
+                    line_number
+                );
+                return Ok(Self::build_toc_item(0, None, &mut items.into_iter().peekable()));
             }
-        });
 
-        let toc = Self::build_toc_item(0, &mut it.peekable());
+            items.push(Self::parse_line(&line, line_number, i, page_numbers)?);
+        }
 
-        toc
+        if let Some(blank) = pending_blank {
+            warn!(
+                "ignoring {} trailing empty lines in tree.dki",
+                line_count - blank + 1
+            );
+        }
+
+        if items.len() != page_numbers.len() {
+            warn!(
+                "tree.dki has {} entries but tree.dka has {} page numbers",
+                items.len(),
+                page_numbers.len()
+            );
+        }
+
+        Ok(Self::build_toc_item(0, None, &mut items.into_iter().peekable()))
+    }
+
+    fn parse_line(
+        line: &str,
+        line_number: usize,
+        idx: usize,
+        page_numbers: &[i32],
+    ) -> eyre::Result<TocItem> {
+        let indent = line
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect::<String>();
+
+        if indent.contains(' ') && indent.contains('\t') {
+            warn!(
+                "tree.dki line {}: mixed tab and space indentation, counting each as one level",
+                line_number
+            );
+        }
+
+        let level = u8::try_from(indent.chars().count() + 1).map_err(|_| {
+            eyre!(
+                "tree.dki line {}: indentation of {} is too deep",
+                line_number,
+                indent.chars().count()
+            )
+        })?;
+
+        let page_number = if idx == 0 { 1 } else { page_numbers[idx - 1] };
+        let end = page_numbers[idx];
+
+        if page_number < 1 || end < page_number {
+            return Err(eyre!(
+                "tree.dki line {}: TOC entry has invalid page range {}..{}",
+                line_number,
+                page_number,
+                end
+            ));
+        }
+
+        Ok(TocItem {
+            id: idx,
+            title: line[indent.len()..].trim().to_owned(),
+            level,
+            page_number: page_number as usize,
+            page_count: (end - page_number) as usize,
+            parent: None,
+            children: Vec::new(),
+        })
     }
 
     fn build_toc_item(
         level: u8,
+        parent: Option<usize>,
         rest: &mut Peekable<impl Iterator<Item = TocItem>>,
     ) -> Vec<TocItem> {
         let mut children = Vec::new();
@@ -66,29 +169,323 @@ impl Toc {
         loop {
             let Some(mut next) = rest.next_if(|next| level < next.level) else { return children; };
 
-            next.children = Self::build_toc_item(next.level, rest);
+            next.parent = parent;
+            next.children = Self::build_toc_item(next.level, Some(next.id), rest);
             children.push(next);
         }
     }
+
+    /// Find the entry with the given id, which is the last entry of its path.
+    pub fn get(&self, id: usize) -> Option<&TocItem> {
+        self.path(id).pop()
+    }
+
+    /// The chain of entries from the top level down to the entry with the given id.
+    ///
+    /// Ids are assigned in depth-first order, so at each level the entry we're
+    /// looking for is inside the last sibling whose id is not greater than it.
+    pub fn path(&self, id: usize) -> Vec<&TocItem> {
+        let mut path = Vec::new();
+        let mut entries = &self.entries;
+
+        loop {
+            let idx = entries.partition_point(|e| e.id <= id);
+            let Some(entry) = idx.checked_sub(1).map(|i| &entries[i]) else { return Vec::new(); };
+
+            path.push(entry);
+
+            if entry.id == id {
+                return path;
+            }
+
+            entries = &entry.children;
+        }
+    }
+
+    /// Find an entry by the titles on the way to it, starting at the top level.
+    ///
+    /// Titles are compared case-insensitively.
+    pub fn find_path<'a>(&self, titles: impl IntoIterator<Item = &'a str>) -> Option<&TocItem> {
+        let mut entries = &self.entries;
+        let mut found = None;
+
+        for title in titles {
+            let title = title.trim();
+            let entry = entries
+                .iter()
+                .find(|e| e.title.to_lowercase() == title.to_lowercase())?;
+
+            entries = &entry.children;
+            found = Some(entry);
+        }
+
+        found
+    }
+
+    pub fn parent(&self, item: &TocItem) -> Option<&TocItem> {
+        self.get(item.parent?)
+    }
+
+    /// Find the entry whose own pages contain the given page.
+    ///
+    /// Each page belongs to exactly one entry: a parent's range stops where its
+    /// first child starts.
+    pub fn entry_for_page(&self, page: usize) -> Option<&TocItem> {
+        let mut entries = &self.entries;
+
+        loop {
+            let idx = entries.partition_point(|e| e.page_number <= page);
+            let entry = &entries[idx.checked_sub(1)?];
+
+            if entry.pages().contains(&page) {
+                return Some(entry);
+            }
+
+            entries = &entry.children;
+        }
+    }
+
+    pub fn path_for_page(&self, page: usize) -> Vec<&TocItem> {
+        self.entry_for_page(page)
+            .map_or_else(Vec::new, |e| self.path(e.id))
+    }
+
+    pub fn iter_depth_first(&self) -> DepthFirst<'_> {
+        DepthFirst {
+            stack: vec![self.entries.iter()],
+        }
+    }
+
+    pub fn iter_breadth_first(&self) -> BreadthFirst<'_> {
+        BreadthFirst {
+            queue: self.entries.iter().collect(),
+        }
+    }
+
+    /// Case-insensitive title search, best matches first.
+    ///
+    /// Exact matches rank above prefix matches, which rank above substring
+    /// matches, which rank above titles containing the query's characters in
+    /// order. Equally good matches are ordered level by level, so a part comes
+    /// before the chapters in it.
+    pub fn search_title(&self, query: &str) -> Vec<&TocItem> {
+        let query = query.to_lowercase();
+
+        let mut hits = self
+            .iter_breadth_first()
+            .filter_map(|e| {
+                let title = e.title.to_lowercase();
+
+                let score = if title == query {
+                    0
+                } else if title.starts_with(&query) {
+                    1
+                } else if title.contains(&query) {
+                    2
+                } else if is_subsequence(&query, &title) {
+                    3
+                } else {
+                    return None;
+                };
+
+                Some((score, e))
+            })
+            .collect::<Vec<_>>();
+
+        hits.sort_by_key(|(score, _)| *score);
+
+        hits.into_iter().map(|(_, e)| e).collect()
+    }
 }
 
-#[derive(Debug)]
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+pub struct DepthFirst<'a> {
+    stack: Vec<std::slice::Iter<'a, TocItem>>,
+}
+
+impl<'a> Iterator for DepthFirst<'a> {
+    type Item = &'a TocItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let top = self.stack.last_mut()?;
+
+            if let Some(item) = top.next() {
+                self.stack.push(item.children.iter());
+                return Some(item);
+            }
+
+            self.stack.pop();
+        }
+    }
+}
+
+pub struct BreadthFirst<'a> {
+    queue: VecDeque<&'a TocItem>,
+}
+
+impl<'a> Iterator for BreadthFirst<'a> {
+    type Item = &'a TocItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.queue.pop_front()?;
+        self.queue.extend(item.children.iter());
+        Some(item)
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
 pub struct TocItem {
     pub id: usize,
     pub title: String,
     pub level: u8,
     pub page_number: usize,
     pub page_count: usize,
+    #[serde(skip)]
+    pub parent: Option<usize>,
     pub children: Vec<TocItem>,
 }
 
-#[derive(Debug)]
+impl TocItem {
+    /// The pages belonging to this entry itself, excluding its children.
+    pub fn pages(&self) -> Range<usize> {
+        self.page_number..(self.page_number + self.page_count)
+    }
+}
+
 #[binrw::binrw]
-#[br(little)]
+#[derive(Debug)]
+#[brw(little)]
 struct DkaBlock {
-    #[br(map = |x: u32| x + 1)]
+    #[br(temp, map = |x: u32| x + 1)]
+    #[bw(calc = block.len() as u32 - 1)]
     len: u32,
 
     #[br(count = len)]
     block: Vec<i32>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Volume
+    //  Part A       (pages 1..3)
+    //   Chapter 1   (pages 3..10)
+    //   Chapter 2   (pages 10..12)
+    //  Part B       (pages 12..12)
+    //   Chapter 3   (pages 12..20)
+    fn toc() -> Toc {
+        let lines = [
+            "Volume",
+            " Part A",
+            "  Chapter 1",
+            "  Chapter 2",
+            " Part B",
+            "  Chapter 3",
+        ];
+        let page_numbers = [1, 3, 10, 12, 12, 20];
+
+        let entries = Toc::ingest(lines.map(|l| Ok(l.to_owned())), &page_numbers).unwrap();
+
+        Toc { entries }
+    }
+
+    fn titles<'a>(items: impl IntoIterator<Item = &'a TocItem>) -> Vec<&'a str> {
+        items.into_iter().map(|e| e.title.as_str()).collect()
+    }
+
+    #[test]
+    fn ingest_builds_tree() {
+        let toc = toc();
+
+        assert_eq!(titles(&toc.entries), ["Volume"]);
+        assert_eq!(titles(&toc.entries[0].children), ["Part A", "Part B"]);
+        assert_eq!(
+            titles(&toc.entries[0].children[0].children),
+            ["Chapter 1", "Chapter 2"]
+        );
+        assert_eq!(toc.entries[0].children[1].children[0].pages(), 12..20);
+    }
+
+    #[test]
+    fn ingest_tolerates_tabs_and_trailing_blanks() {
+        let lines = ["Volume", "\tPart A", "\t\tChapter 1", "", "  "];
+        let entries = Toc::ingest(lines.map(|l| Ok(l.to_owned())), &[1, 2, 3]).unwrap();
+
+        assert_eq!(entries[0].children[0].children[0].level, 3);
+    }
+
+    #[test]
+    fn ingest_reports_line_numbers() {
+        let lines = ["Volume", "", " Part A"];
+        let err = Toc::ingest(lines.map(|l| Ok(l.to_owned())), &[1, 2, 3]).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+
+        let lines = ["Volume", " Part A"];
+        let err = Toc::ingest(lines.map(|l| Ok(l.to_owned())), &[5, 3]).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn lookup_by_page() {
+        let toc = toc();
+
+        assert_eq!(toc.entry_for_page(1).unwrap().title, "Part A");
+        assert_eq!(toc.entry_for_page(3).unwrap().title, "Chapter 1");
+        assert_eq!(toc.entry_for_page(11).unwrap().title, "Chapter 2");
+        assert_eq!(toc.entry_for_page(12).unwrap().title, "Chapter 3");
+        assert!(toc.entry_for_page(20).is_none());
+        assert!(toc.entry_for_page(0).is_none());
+
+        assert_eq!(
+            titles(toc.path_for_page(15)),
+            ["Volume", "Part B", "Chapter 3"]
+        );
+    }
+
+    #[test]
+    fn lookup_by_id_and_parent() {
+        let toc = toc();
+
+        let chapter = toc.get(3).unwrap();
+        assert_eq!(chapter.title, "Chapter 2");
+        assert_eq!(toc.parent(chapter).unwrap().title, "Part A");
+        assert!(toc.parent(toc.get(0).unwrap()).is_none());
+        assert!(toc.get(6).is_none());
+
+        assert_eq!(titles(toc.path(5)), ["Volume", "Part B", "Chapter 3"]);
+    }
+
+    #[test]
+    fn iterators() {
+        let toc = toc();
+
+        assert_eq!(
+            titles(toc.iter_depth_first()),
+            ["Volume", "Part A", "Chapter 1", "Chapter 2", "Part B", "Chapter 3"]
+        );
+        assert_eq!(
+            titles(toc.iter_breadth_first()),
+            ["Volume", "Part A", "Part B", "Chapter 1", "Chapter 2", "Chapter 3"]
+        );
+    }
+
+    #[test]
+    fn title_search() {
+        let toc = toc();
+
+        assert_eq!(
+            titles(toc.search_title("chapter")),
+            ["Chapter 1", "Chapter 2", "Chapter 3"]
+        );
+        assert_eq!(titles(toc.search_title("part b")), ["Part B"]);
+        assert_eq!(titles(toc.search_title("chptr 2")), ["Chapter 2"]);
+        assert!(toc.search_title("nothing").is_empty());
+    }
+}
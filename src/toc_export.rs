@@ -0,0 +1,233 @@
+use std::io::Write;
+
+use eyre::eyre;
+
+use crate::toc::{Toc, TocItem};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TocFormat {
+    Text,
+    Json,
+    Opml,
+    Csv,
+}
+
+pub fn export(toc: &Toc, format: TocFormat, out: impl Write) -> eyre::Result<()> {
+    match format {
+        TocFormat::Text => write_text(toc, out),
+        TocFormat::Json => write_json(toc, out),
+        TocFormat::Opml => write_opml(toc, out),
+        TocFormat::Csv => write_csv(toc, out),
+    }
+}
+
+/// Print the entries containing the page, from the top level down.
+pub fn write_page_path(toc: &Toc, page: usize, mut out: impl Write) -> eyre::Result<()> {
+    let path = toc.path_for_page(page);
+
+    if path.is_empty() {
+        return Err(eyre!("no TOC entry contains page {}", page));
+    }
+
+    for item in path {
+        writeln!(
+            out,
+            "{:indent$}{} [{}]",
+            "",
+            item.title,
+            item.id,
+            indent = (item.level as usize - 1) * 2
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Print the entries whose titles match the query, best matches first, each
+/// with the entry it belongs to.
+pub fn write_search(toc: &Toc, query: &str, mut out: impl Write) -> eyre::Result<()> {
+    for item in toc.search_title(query) {
+        let pages = item.pages();
+        write!(out, "{} [{}] (pages {}..{})", item.title, item.id, pages.start, pages.end)?;
+
+        if let Some(parent) = toc.parent(item) {
+            write!(out, " in {}", parent.title)?;
+        }
+
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+fn write_text(toc: &Toc, mut out: impl Write) -> eyre::Result<()> {
+    for item in toc.iter_depth_first() {
+        let pages = item.pages();
+        writeln!(
+            out,
+            "{:indent$}{} [{}] (pages {}..{})",
+            "",
+            item.title,
+            item.id,
+            pages.start,
+            pages.end,
+            indent = (item.level as usize - 1) * 2
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_json(toc: &Toc, mut out: impl Write) -> eyre::Result<()> {
+    serde_json::to_writer_pretty(&mut out, &toc.entries)?;
+    writeln!(out)?;
+
+    Ok(())
+}
+
+fn write_opml(toc: &Toc, mut out: impl Write) -> eyre::Result<()> {
+    fn write_outline(item: &TocItem, depth: usize, out: &mut impl Write) -> eyre::Result<()> {
+        write!(
+            out,
+            "{:indent$}<outline text=\"{}\" id=\"{}\" pageNumber=\"{}\" pageCount=\"{}\"",
+            "",
+            escape_xml(&item.title),
+            item.id,
+            item.page_number,
+            item.page_count,
+            indent = depth * 2
+        )?;
+
+        if item.children.is_empty() {
+            writeln!(out, "/>")?;
+        } else {
+            writeln!(out, ">")?;
+            for child in &item.children {
+                write_outline(child, depth + 1, out)?;
+            }
+            writeln!(out, "{:indent$}</outline>", "", indent = depth * 2)?;
+        }
+
+        Ok(())
+    }
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<opml version="2.0">"#)?;
+    writeln!(out, "  <head><title>Table of contents</title></head>")?;
+    writeln!(out, "  <body>")?;
+
+    for item in &toc.entries {
+        write_outline(item, 2, &mut out)?;
+    }
+
+    writeln!(out, "  </body>")?;
+    writeln!(out, "</opml>")?;
+
+    Ok(())
+}
+
+/// One row per entry, page ranges are half-open like `TocItem::pages`.
+fn write_csv(toc: &Toc, mut out: impl Write) -> eyre::Result<()> {
+    writeln!(out, "id,level,title,page_start,page_end,child_count")?;
+
+    for item in toc.iter_depth_first() {
+        let pages = item.pages();
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            item.id,
+            item.level,
+            escape_csv(&item.title),
+            pages.start,
+            pages.end,
+            item.children.len()
+        )?;
+    }
+
+    Ok(())
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_csv(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toc() -> Toc {
+        let lines = ["Werke", " Faust", "  Erster Teil", " Gedichte, Balladen"];
+        let entries = Toc::ingest(lines.map(|l| Ok(l.to_owned())), &[2, 3, 4, 5]).unwrap();
+
+        Toc { entries }
+    }
+
+    fn export_string(format: TocFormat) -> String {
+        let mut out = Vec::new();
+        export(&toc(), format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json() {
+        let json: serde_json::Value = serde_json::from_str(&export_string(TocFormat::Json)).unwrap();
+
+        let werke = &json[0];
+        assert_eq!(werke["title"], "Werke");
+        assert_eq!(werke["children"].as_array().unwrap().len(), 2);
+
+        let faust = &werke["children"][0];
+        assert_eq!(faust["title"], "Faust");
+        assert_eq!(faust["level"], 2);
+        assert_eq!(faust["children"][0]["title"], "Erster Teil");
+        assert_eq!(faust["children"][0]["page_number"], 3);
+        assert!(werke["children"][1]["children"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn text_and_csv() {
+        assert_eq!(
+            export_string(TocFormat::Text),
+            "Werke [0] (pages 1..2)\n  Faust [1] (pages 2..3)\n    Erster Teil [2] (pages 3..4)\n  Gedichte, Balladen [3] (pages 4..5)\n"
+        );
+
+        let csv = export_string(TocFormat::Csv);
+        let rows = csv.lines().collect::<Vec<_>>();
+        assert_eq!(rows[0], "id,level,title,page_start,page_end,child_count");
+        assert_eq!(rows[1], "0,1,Werke,1,2,2");
+        assert_eq!(rows[4], "3,2,\"Gedichte, Balladen\",4,5,0");
+    }
+
+    #[test]
+    fn opml_nesting() {
+        let opml = export_string(TocFormat::Opml);
+        let faust = opml.find("text=\"Faust\"").unwrap();
+        let teil = opml.find("text=\"Erster Teil\"").unwrap();
+        let close = opml[faust..].find("</outline>").unwrap() + faust;
+
+        assert!(faust < teil && teil < close);
+    }
+
+    #[test]
+    fn page_path_and_search() {
+        let mut out = Vec::new();
+        write_page_path(&toc(), 3, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Werke [0]\n  Faust [1]\n    Erster Teil [2]\n");
+        assert!(write_page_path(&toc(), 9, Vec::new()).is_err());
+
+        let mut out = Vec::new();
+        write_search(&toc(), "teil", &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Erster Teil [2] (pages 3..4) in Faust\n");
+    }
+}
@@ -1,219 +1,256 @@
 use std::fmt::Debug;
 
-#[binrw::binread]
-#[br(little)]
+#[binrw::binrw]
+#[brw(little)]
+#[derive(Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
 pub struct Name {
     #[br(temp)]
+    #[bw(try_calc = encode_1252(data).and_then(|d| name_len(d.len())))]
     len: u8,
 
-    #[br(count = len, map = |buf: Vec<u8>| encoding_rs::WINDOWS_1252.decode(&buf).0.to_string() )]
+    #[br(args { count: usize::from(len), inner: () }, map = |buf: Vec<u8>| encoding_rs::WINDOWS_1252.decode_without_bom_handling(&buf).0.to_string() )]
+    #[bw(try_map = |s: &String| encode_1252(s))]
     pub data: String,
 }
 
+/// Encode text as Windows-1252. Characters it doesn't have are an error
+/// instead of being written as numeric character references.
+fn encode_1252(s: &str) -> Result<Vec<u8>, String> {
+    let (bytes, _, had_errors) = encoding_rs::WINDOWS_1252.encode(s);
+
+    if had_errors {
+        return Err(format!("{:?} can't be encoded as Windows-1252", s));
+    }
+
+    Ok(bytes.into_owned())
+}
+
+fn name_len(len: usize) -> Result<u8, String> {
+    u8::try_from(len).map_err(|_| format!("a name of {} bytes is too long, the limit is 255", len))
+}
+
+/// The length byte of a word, which has the space flag in its top bit.
+fn word_len(len: usize, space_at_end: bool) -> Result<u8, String> {
+    match u8::try_from(len) {
+        Ok(len) if len <= 0x7f => Ok(len | if space_at_end { 0x80 } else { 0 }),
+        _ => Err(format!("a word of {} bytes is too long, the limit is 127", len)),
+    }
+}
+
 impl Debug for Name {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_tuple("Name").field(&self.data).finish()
     }
 }
 
-#[binrw::binread]
-#[derive(Debug)]
-#[br(little)]
+#[binrw::binrw]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[brw(little)]
 pub enum Token {
-    #[br(magic = 0u8)]
+    #[brw(magic = 0u8)]
     Blanks(u8),
-    #[br(magic = 1u8)]
+    #[brw(magic = 1u8)]
     Word {
         #[br(temp)]
+        #[bw(try_calc = word_len(data.len(), *space_at_end))]
         len: u8,
 
         #[br(calc = len & 0x80 != 0)]
+        #[bw(ignore)]
         space_at_end: bool,
 
-        #[br(count = len & 0x7f)]
+        #[br(args { count: usize::from(len & 0x7f), inner: () })]
         data: Vec<u8>,
     },
-    #[br(magic = 2u8)]
+    #[brw(magic = 2u8)]
     HardCarriageReturn,
-    #[br(magic = 3u8)]
+    #[brw(magic = 3u8)]
     EndOfPage,
-    #[br(magic = 4u8)]
+    #[brw(magic = 4u8)]
     ItalicsOn,
-    #[br(magic = 5u8)]
+    #[brw(magic = 5u8)]
     ItalicsOff,
-    #[br(magic = 6u8)]
+    #[brw(magic = 6u8)]
     BoldOn,
-    #[br(magic = 7u8)]
+    #[brw(magic = 7u8)]
     BoldOff,
-    #[br(magic = 8u8)]
+    #[brw(magic = 8u8)]
     FontPreset(u8),
-    #[br(magic = 9u8)]
+    #[brw(magic = 9u8)]
     Ly,
-    #[br(magic = 10u8)]
+    #[brw(magic = 10u8)]
     Image { width: u32, name: Name },
-    #[br(magic = 11u8)]
+    #[brw(magic = 11u8)]
     ImageLink(Name),
-    #[br(magic = 12u8)]
+    #[brw(magic = 12u8)]
     EndLink,
-    #[br(magic = 13u8)]
+    #[brw(magic = 13u8)]
     Font(u8),
-    #[br(magic = 14u8)]
+    #[brw(magic = 14u8)]
     FileName(Name),
-    #[br(magic = 15u8)]
+    #[brw(magic = 15u8)]
     Concordance(u16),
-    #[br(magic = 16u8)]
+    #[brw(magic = 16u8)]
     NodeNumber(u16),
-    #[br(magic = 17u8)]
+    #[brw(magic = 17u8)]
     SuperScriptOn,
-    #[br(magic = 18u8)]
+    #[brw(magic = 18u8)]
     SuperScriptOff,
-    #[br(magic = 19u8)]
+    #[brw(magic = 19u8)]
     Sigil(Name),
-    #[br(magic = 20u8)]
+    #[brw(magic = 20u8)]
     Header,
-    #[br(magic = 21u8)]
+    #[brw(magic = 21u8)]
     HypenAtEol,
-    #[br(magic = 22u8)]
+    #[brw(magic = 22u8)]
     UnderlineOn,
-    #[br(magic = 23u8)]
+    #[brw(magic = 23u8)]
     UnderlineOff,
-    #[br(magic = 24u8)]
+    #[brw(magic = 24u8)]
     GreekOn,
-    #[br(magic = 25u8)]
+    #[brw(magic = 25u8)]
     GreekOff,
-    #[br(magic = 27u8)]
+    #[brw(magic = 27u8)]
     OneBlank,
-    #[br(magic = 28u8)]
+    #[brw(magic = 28u8)]
     VerticalLineOn,
-    #[br(magic = 29u8)]
+    #[brw(magic = 29u8)]
     VerticalLineOff,
-    #[br(magic = 30u8)]
+    #[brw(magic = 30u8)]
     TD,
-    #[br(magic = 31u8)]
+    #[brw(magic = 31u8)]
     Null,
-    #[br(magic = 128u8)]
+    #[brw(magic = 128u8)]
     PageLink { page_number: u32, name: Name },
-    #[br(magic = 129u8)]
+    #[brw(magic = 129u8)]
     IDStart(u8),
-    #[br(magic = 130u8)]
+    #[brw(magic = 130u8)]
     IDEnd(u8),
-    #[br(magic = 131u8)]
+    #[brw(magic = 131u8)]
     SubscriptOn,
-    #[br(magic = 132u8)]
+    #[brw(magic = 132u8)]
     SubscriptOff,
-    #[br(magic = 133u8)]
+    #[brw(magic = 133u8)]
     Color(u8),
-    #[br(magic = 134u8)]
+    #[brw(magic = 134u8)]
     InlineImage { width: u16, height: u16, name: Name },
-    #[br(magic = 135u8)]
+    #[brw(magic = 135u8)]
     SearchWord(Name),
-    #[br(magic = 136u8)]
+    #[brw(magic = 136u8)]
     FontSize(u8),
-    #[br(magic = 137u8)]
+    #[brw(magic = 137u8)]
     Copyright(u8),
-    #[br(magic = 138u8)]
+    #[brw(magic = 138u8)]
     AutoLink(u32),
-    #[br(magic = 139u8)]
+    #[brw(magic = 139u8)]
     SoftCarriageReturn,
-    #[br(magic = 140u8)]
+    #[brw(magic = 140u8)]
     InvisibleHyphen,
-    #[br(magic = 141u8)]
+    #[brw(magic = 141u8)]
     LetterSpacingOn,
-    #[br(magic = 142u8)]
+    #[brw(magic = 142u8)]
     LetterSpacingOff,
-    #[br(magic = 143u8)]
+    #[brw(magic = 143u8)]
     HalfLineSpacing,
-    #[br(magic = 144u8)]
+    #[brw(magic = 144u8)]
     ListItemStart,
-    #[br(magic = 145u8)]
+    #[brw(magic = 145u8)]
     ListItemEnd,
-    #[br(magic = 146u8)]
+    #[brw(magic = 146u8)]
     UnorderedListStart,
-    #[br(magic = 147u8)]
+    #[brw(magic = 147u8)]
     UnorderedListEnd,
-    #[br(magic = 148u8)]
+    #[brw(magic = 148u8)]
     SetX(u16),
-    #[br(magic = 149u8)]
+    #[brw(magic = 149u8)]
     SV(u64),
-    #[br(magic = 150u8)]
+    #[brw(magic = 150u8)]
     SVLemmaBegin(Name),
-    #[br(magic = 151u8)]
+    #[brw(magic = 151u8)]
     SVLemmaStop,
-    #[br(magic = 152u8)]
+    #[brw(magic = 152u8)]
     CenteredOn,
-    #[br(magic = 153u8)]
+    #[brw(magic = 153u8)]
     CenteredOff,
-    #[br(magic = 154u8)]
+    #[brw(magic = 154u8)]
     AlignRightOn,
-    #[br(magic = 155u8)]
+    #[brw(magic = 155u8)]
     AlignRightOff,
-    #[br(magic = 156u8)]
+    #[brw(magic = 156u8)]
     EOn,
-    #[br(magic = 157u8)]
+    #[brw(magic = 157u8)]
     EOff,
-    #[br(magic = 158u8)]
+    #[brw(magic = 158u8)]
     BibIndex(u32),
-    #[br(magic = 159u8)]
+    #[brw(magic = 159u8)]
     NotFirstLine,
-    #[br(magic = 160u8)]
+    #[brw(magic = 160u8)]
     Thumb,
-    #[br(magic = 161u8)]
+    #[brw(magic = 161u8)]
     EndNew([u8; 3]),
-    #[br(magic = 162u8)]
+    #[brw(magic = 162u8)]
     UrlBegin(Name),
-    #[br(magic = 163u8)]
+    #[brw(magic = 163u8)]
     UrlEnd,
-    #[br(magic = 164u8)]
+    #[brw(magic = 164u8)]
     WordAnchor,
-    #[br(magic = 165u8)]
+    #[brw(magic = 165u8)]
     ThumbWWW,
-    #[br(magic = 166u8)]
+    #[brw(magic = 166u8)]
     S,
-    #[br(magic = 167u8)]
+    #[brw(magic = 167u8)]
     NoJustifyOn,
-    #[br(magic = 168u8)]
+    #[brw(magic = 168u8)]
     NoJustifyOff,
-    #[br(magic = 169u8)]
+    #[brw(magic = 169u8)]
     NextBlankFixed,
-    #[br(magic = 170u8)]
+    #[brw(magic = 170u8)]
     WordRest {
         #[br(temp)]
+        #[bw(try_calc = encode_1252(data).and_then(|d| word_len(d.len(), *space_at_end)))]
         len: u8,
 
         #[br(calc = len & 0x80 != 0)]
+        #[bw(ignore)]
         space_at_end: bool,
 
-        #[br(count = len, map = |buf: Vec<u8>| encoding_rs::WINDOWS_1252.decode(&buf).0.to_string() )]
+        #[br(args { count: usize::from(len & 0x7f), inner: () }, map = |buf: Vec<u8>| encoding_rs::WINDOWS_1252.decode_without_bom_handling(&buf).0.to_string() )]
+        #[bw(try_map = |s: &String| encode_1252(s))]
         data: String,
     },
-    #[br(magic = 171u8)]
+    #[brw(magic = 171u8)]
     WordIncomplete(Name),
-    #[br(magic = 172u8)]
+    #[brw(magic = 172u8)]
     HyphenCK,
-    #[br(magic = 173u8)]
+    #[brw(magic = 173u8)]
     HebrewOn,
-    #[br(magic = 174u8)]
+    #[brw(magic = 174u8)]
     HebrewOff,
-    #[br(magic = 175u8)]
+    #[brw(magic = 175u8)]
     NodeNumber2(u32),
-    #[br(magic = 176u8)]
+    #[brw(magic = 176u8)]
     StrikeThroughOn,
-    #[br(magic = 177u8)]
+    #[brw(magic = 177u8)]
     StrikeThroughOff,
-    #[br(magic = 178u8)]
+    #[brw(magic = 178u8)]
     SetY(u16),
-    #[br(magic = 179u8)]
+    #[brw(magic = 179u8)]
     Cor(u32),
-    #[br(magic = 180u8)]
+    #[brw(magic = 180u8)]
     EndCor,
-    #[br(magic = 236u8)]
+    #[brw(magic = 236u8)]
     DashedLine,
+    // `Page::lex` collects bytes it can't parse into these, so writing one
+    // gives back exactly those bytes. The magic only stops this variant from
+    // matching anything else, a 0xff byte is unparseable like any other.
     #[br(magic = 255u8)]
     Unknown {
         #[br(count = 0)]
         raw: Vec<u8>,
 
         #[br(count = 0, map = |_: Vec<u8>| String::new())]
+        #[bw(ignore)]
         decoded: String,
     },
 }
@@ -6,12 +6,41 @@ use std::{
 
 use once_cell::sync::{Lazy, OnceCell};
 use regex::Regex;
+use tracing::warn;
+
+use crate::{
+    anchor::AnchorIds,
+    decoding,
+    encoder::{self, Context},
+    links::{self, BrokenLink},
+    normalize::Normalizer,
+    split::Location,
+    text::Page,
+    toc::TocItem,
+    token::Token,
+};
 
-use crate::{decoding, text::Page, toc::TocItem, token::Token};
+static ESCAPER: Lazy<Regex> = Lazy::new(|| Regex::new(r"[#()\[\]*=_`<>/$]").unwrap());
 
-pub struct State<W> {
+pub struct State<'a, W> {
     writer: W,
+    normalizer: &'a Normalizer,
+    /// Text not yet written, so that it's normalized as a whole and marks can
+    /// compose with the letter before them
+    pending_text: String,
+    /// Where the last word starts in `pending_text`, to take it back if it's
+    /// hyphenated at the end of the page
+    last_word: Option<usize>,
+    /// A fragment carried over from the previous page, not yet joined
+    carried: Option<String>,
     font_idx: u8,
+    list_depth: usize,
+    anchor_ids: AnchorIds,
+    /// The number of columns of the table being written, 0 outside tables
+    table_columns: usize,
+    row_cells: usize,
+    greek: bool,
+    hebrew: bool,
     word_incomplete: bool,
     had_carriage_return: bool,
     add_hyphen_at_eol: bool,
@@ -24,11 +53,21 @@ pub struct State<W> {
     current_functions: Vec<(&'static str, String)>,
 }
 
-impl<W: Write> State<W> {
-    fn new(writer: W) -> Self {
+impl<'a, W: Write> State<'a, W> {
+    fn new(writer: W, normalizer: &'a Normalizer) -> Self {
         Self {
             writer,
+            normalizer,
+            pending_text: String::new(),
+            last_word: None,
+            carried: None,
             font_idx: 0,
+            list_depth: 0,
+            anchor_ids: AnchorIds::default(),
+            table_columns: 0,
+            row_cells: 0,
+            greek: false,
+            hebrew: false,
             word_incomplete: false,
             had_carriage_return: false,
             add_hyphen_at_eol: false,
@@ -61,6 +100,66 @@ impl<W: Write> State<W> {
         Ok(())
     }
 
+    /// Close the open functions without forgetting them, so they can be
+    /// reopened in the next table cell.
+    fn suspend_states(&mut self) -> eyre::Result<()> {
+        self.flush_text()?;
+
+        for _ in 0..self.current_functions.len() {
+            self.writer.write_str("]")?;
+        }
+
+        Ok(())
+    }
+
+    fn resume_states(&mut self) -> eyre::Result<()> {
+        for (_, v) in &self.current_functions {
+            write!(self.writer, "#{}[", v)?;
+        }
+
+        Ok(())
+    }
+
+    fn table_cell(&mut self, rest: &[Token]) -> eyre::Result<()> {
+        self.suspend_states()?;
+
+        if self.table_columns == 0 {
+            self.table_columns = encoder::table_columns(rest).max(1);
+            write!(self.writer, "\n#table(columns: {}, stroke: none,\n", self.table_columns)?;
+        } else if self.row_cells > 0 {
+            self.writer.write_str("], ")?;
+        }
+
+        self.writer.write_str("[")?;
+        self.row_cells += 1;
+
+        self.resume_states()
+    }
+
+    /// End the table row, padding it to the table's width, and the table too
+    /// if no cells follow.
+    fn end_table_row(&mut self, rest: &[Token]) -> eyre::Result<()> {
+        self.suspend_states()?;
+
+        if self.row_cells > 0 {
+            self.writer.write_str("],")?;
+        }
+
+        for _ in self.row_cells..self.table_columns {
+            self.writer.write_str(" [],")?;
+        }
+
+        self.writer.write_str("\n")?;
+        self.row_cells = 0;
+
+        if encoder::row_cells(rest) == 0 {
+            self.writer.write_str(")\n")?;
+            self.table_columns = 0;
+        }
+
+        self.resume_states()
+    }
+
     fn pop_all_states(&mut self) -> eyre::Result<()> {
         for _ in 0..self.current_functions.len() {
             write!(self, "]")?;
@@ -99,11 +198,70 @@ impl<W: Write> State<W> {
 
         Ok(())
     }
+
+    /// Queue text to be escaped and written before the next markup.
+    fn text(&mut self, s: &str) {
+        self.pending_text.push_str(s);
+    }
+
+    /// Write a word, joining it to the one hyphenated before it, whether that
+    /// is still waiting to be written or was carried over from the previous
+    /// page.
+    fn write_word(&mut self, s: &str) {
+        // historical German hyphenation splits ck as k-k
+        let split_ck = self.add_hyphen_at_eol_separating_ck && s.starts_with('k');
+        let ck = |fragment: &mut String| {
+            if split_ck && fragment.ends_with('k') {
+                fragment.pop();
+                fragment.push('c');
+            }
+        };
+
+        let mut fragment = match self.carried.take() {
+            Some(fragment) => fragment,
+            None => match self.last_word.filter(|_| self.hyphen()) {
+                Some(start) => self.pending_text.split_off(start),
+                None => String::new(),
+            },
+        };
+
+        ck(&mut fragment);
+        self.last_word = Some(self.pending_text.len());
+        self.text(&fragment);
+        self.text(s);
+    }
+
+    /// Take back the last word, unless it has been written already.
+    fn take_last_word(&mut self) -> Option<String> {
+        self.last_word.take().map(|start| self.pending_text.split_off(start))
+    }
+
+    fn flush_text(&mut self) -> std::fmt::Result {
+        if self.pending_text.is_empty() {
+            return Ok(());
+        }
+
+        self.last_word = None;
+        let text = std::mem::take(&mut self.pending_text);
+        let text = self.normalizer.apply(&text);
+        self.write_raw(&ESCAPER.replace_all(&text, "\\$0"))
+    }
+
+    fn write_raw(&mut self, s: &str) -> std::fmt::Result {
+        // text in a table row before its first cell gets a cell of its own
+        if self.table_columns > 0 && self.row_cells == 0 && !s.trim().is_empty() {
+            self.writer.write_str("[")?;
+            self.row_cells = 1;
+        }
+
+        self.writer.write_str(s)
+    }
 }
 
-impl<W: Write> Write for State<W> {
+impl<'a, W: Write> Write for State<'a, W> {
     fn write_str(&mut self, s: &str) -> std::fmt::Result {
-        self.writer.write_str(s)
+        self.flush_text()?;
+        self.write_raw(s)
     }
 }
 
@@ -138,15 +296,31 @@ pub const PREFIX: &'static str = r###"
 }}
 "###;
 
+pub fn write_preamble(title: &str, mut output: impl Write) -> eyre::Result<()> {
+    output.write_str(PREFIX)?;
+    writeln!(
+        output,
+        "#show: project.with(title: \"{}\")",
+        title.replace('\\', "\\\\").replace('"', "\\\"")
+    )?;
+
+    Ok(())
+}
+
 pub fn write_page(
     tocitem: &TocItem,
     page_number: usize,
     lexed: &[Token],
+    ctx: &Context,
+    carry: &mut encoder::Carry,
     output: impl Write,
 ) -> eyre::Result<()> {
-    static ESCAPER: Lazy<Regex> = Lazy::new(|| Regex::new(r"[#()\[\]*=_`<>/$]").unwrap());
+    let mut state = State::new(output, ctx.normalizer);
 
-    let mut state = State::new(output);
+    state.add_hyphen_at_eol_separating_ck = carry.separating_ck;
+    state.add_invisible_hyphen = carry.fragment.is_some() && !carry.separating_ck;
+    state.word_incomplete = carry.word_incomplete;
+    state.carried = carry.fragment.take();
 
     writeln!(
         state,
@@ -156,7 +330,7 @@ pub fn write_page(
         page_number
     )?;
 
-    for t in lexed {
+    for (i, t) in lexed.iter().enumerate() {
         match t {
             Token::Blanks(number) => {
                 for _ in 0..*number {
@@ -164,7 +338,13 @@ pub fn write_page(
                 }
             }
             Token::Word { space_at_end, data } => {
-                let s = decoding::decode_string(data, state.font_idx);
+                let s = if state.greek {
+                    decoding::decode_greek(data)
+                } else if state.hebrew {
+                    decoding::decode_hebrew(data)
+                } else {
+                    ctx.fonts.decode(data, state.font_idx)
+                };
 
                 let s = if !state.hyphen() {
                     s.trim_end().trim_end_matches('-')
@@ -176,8 +356,7 @@ pub fn write_page(
                     state.word_incomplete = false;
                 } else {
                     if s.len() > 0 {
-
-                        write!(state, "{}", ESCAPER.replace_all(s, "\\$0"))?;
+                        state.write_word(s);
                     }
                 }
 
@@ -190,7 +369,12 @@ pub fn write_page(
                     write!(state, " ")?;
                 }
             }
+            Token::HardCarriageReturn if state.table_columns > 0 => {
+                state.pop_state("header")?;
+                state.end_table_row(&lexed[i + 1..])?;
+            }
             Token::HardCarriageReturn => {
+                state.pop_state("header")?;
                 state.had_carriage_return = true;
                 writeln!(state, "\\")?;
             }
@@ -264,7 +448,10 @@ pub fn write_page(
             Token::Sigil(s) => {
                 state.sigil = Some(s.data.clone());
             }
-            Token::Header => {}
+            Token::Header => {
+                // running heads last to the end of their line
+                state.push_state("header", "text(size: 0.8em, fill: gray)")?;
+            }
             Token::HypenAtEol => {
                 state.add_invisible_hyphen = true;
             }
@@ -274,8 +461,14 @@ pub fn write_page(
             Token::UnderlineOff => {
                 state.pop_state("underline")?;
             }
-            Token::GreekOn => {}
-            Token::GreekOff => {}
+            Token::GreekOn => {
+                state.greek = true;
+                state.push_state("greek", "text(lang: \"el\")")?;
+            }
+            Token::GreekOff => {
+                state.greek = false;
+                state.pop_state("greek")?;
+            }
             Token::OneBlank => {
                 write!(state, " ")?;
             }
@@ -283,17 +476,24 @@ pub fn write_page(
                 // not used
             }
             Token::VerticalLineOff => {}
-            Token::TD => {}
+            Token::TD => {
+                state.table_cell(&lexed[i..])?;
+            }
             Token::Null => {}
-            Token::PageLink { page_number, name } => {
-                if *page_number != 0 {
-                    write!(state, " @page{} ", page_number)?;
+            Token::PageLink { page_number: target, name } => {
+                if *target != 0 {
+                    write!(state, " ")?;
+                    write_pageref(&mut state, page_number, *target, links::label(&name.data), ctx)?;
+                    write!(state, " ")?;
                 } else {
-                    panic!("I've yet to see an image link");
                     // TODO image link
                 }
             }
-            Token::IDStart(_) => {}
+            Token::IDStart(_) | Token::NodeNumber2(_) | Token::WordAnchor => {
+                if let Some(id) = state.anchor_ids.id(t) {
+                    write!(state, "#metadata(\"{}\") <page{}-{}>", id, page_number, id)?;
+                }
+            }
             Token::IDEnd(_) => {}
             Token::SubscriptOn => {
                 state.push_state("sub", "sub")?;
@@ -320,7 +520,7 @@ pub fn write_page(
             }
             Token::Copyright(_) => {}
             Token::AutoLink(page) => {
-                write!(state, "@page{}", page)?;
+                write_pageref(&mut state, page_number, *page, None, ctx)?;
             }
             Token::SoftCarriageReturn => {
                 if !state.hyphen() {
@@ -340,16 +540,20 @@ pub fn write_page(
                 write!(state, "\n")?;
             }
             Token::ListItemStart => {
-                panic!("actuall saw a list item");
-                write!(state, "[")?;
-            }
-            Token::ListItemEnd => {
-                write!(state, "]")?;
+                // nested items are marked by their indentation
+                let indent = "  ".repeat(state.list_depth.saturating_sub(1));
+                write!(state, "\n{}- ", indent)?;
             }
+            Token::ListItemEnd => {}
             Token::UnorderedListStart => {
+                state.list_depth += 1;
             }
             Token::UnorderedListEnd => {
+                state.list_depth = state.list_depth.saturating_sub(1);
+                writeln!(state)?;
             }
+            // cells are laid out by the table
+            Token::SetX(_) if state.table_columns > 0 => {}
             Token::SetX(indent) => {
                 state.push_state("padding", format!("pad(x: {}pt)", *indent as f32 / 100.0))?;
             }
@@ -376,7 +580,6 @@ pub fn write_page(
             Token::UrlEnd => {
                 state.pop_state("link")?;
             }
-            Token::WordAnchor => {}
             Token::ThumbWWW => {}
             Token::S => {}
             Token::NoJustifyOn => {
@@ -387,28 +590,38 @@ pub fn write_page(
             }
             Token::NextBlankFixed => {}
             Token::WordRest { space_at_end, data } => {
-                write!(state, "{}", data)?;
+                let s = decoding::transliterate(data, state.greek, state.hebrew);
+                state.text(&s);
 
                 if *space_at_end {
                     write!(state, " ")?;
                 }
             }
             Token::WordIncomplete(word) => {
-                write!(state, "{}", word.data)?;
+                let s = decoding::transliterate(&word.data, state.greek, state.hebrew);
+                state.text(&s);
                 state.word_incomplete = true;
             }
             Token::HyphenCK => {
                 state.add_hyphen_at_eol_separating_ck = true;
             }
-            Token::HebrewOn => {}
-            Token::HebrewOff => {}
-            Token::NodeNumber2(_) => {}
+            Token::HebrewOn => {
+                state.hebrew = true;
+                state.push_state("hebrew", "text(dir: rtl, lang: \"he\")")?;
+            }
+            Token::HebrewOff => {
+                state.hebrew = false;
+                state.pop_state("hebrew")?;
+            }
             Token::StrikeThroughOn => {
                 state.push_state("strike", "strikethrough")?;
             }
             Token::StrikeThroughOff => {
                 state.pop_state("strike")?;
             }
+            Token::SetY(_) if state.table_columns > 0 => {
+                state.end_table_row(&lexed[i + 1..])?;
+            }
             Token::SetY(_) => {}
             Token::Cor(_) => {}
             Token::EndCor => {}
@@ -417,6 +630,20 @@ pub fn write_page(
         }
     }
 
+    // a fragment nothing joined onto is written as it is
+    if let Some(fragment) = state.carried.take() {
+        state.text(&fragment);
+    }
+
+    // a word hyphenated at the end of the page is written whole on the next one
+    carry.fragment = if state.hyphen() { state.take_last_word() } else { None };
+    carry.separating_ck = carry.fragment.is_some() && state.add_hyphen_at_eol_separating_ck;
+    carry.word_incomplete = state.word_incomplete;
+
+    if state.table_columns > 0 {
+        state.end_table_row(&[])?;
+    }
+
     state.pop_all_states()?;
 
     writeln!(state, "\n#pagebreak(weak: true)")?;
@@ -424,6 +651,38 @@ pub fn write_page(
     Ok(())
 }
 
+fn write_pageref(
+    state: &mut State<'_, impl Write>,
+    source: usize,
+    page: u32,
+    label: Option<&str>,
+    ctx: &Context,
+) -> eyre::Result<()> {
+    let Some(target) = ctx.links.resolve(page, label) else {
+        // a label pointing nowhere would keep the document from compiling
+        warn!(
+            "{}",
+            BrokenLink {
+                source,
+                target: page,
+                label: label.map(|l| l.to_owned()),
+            }
+        );
+        return Ok(());
+    };
+
+    match (ctx.works.locate(page), target.anchor) {
+        // labels only resolve within a document, so point at the other work's pdf instead
+        (Location::Work(name), _) => write!(state, "#link(\"{}.pdf\")[{}]", name, page)?,
+        // the page isn't written anywhere, so there's no label to refer to
+        (Location::Excluded, _) => write!(state, "{}", page)?,
+        (Location::Current, Some(anchor)) => write!(state, "#link(<page{}-{}>)[{}]", page, anchor, page)?,
+        (Location::Current, None) => write!(state, "@page{}", page)?,
+    }
+
+    Ok(())
+}
+
 pub fn count_delimeters(s: &str, hyphen: bool) -> usize {
     let v = if !hyphen && !s.chars().all(|c| !c.is_alphanumeric()) {
         1
@@ -447,3 +706,129 @@ pub fn count_delimeters(s: &str, hyphen: bool) -> usize {
 
     v + it.filter(|c| !c.is_alphanumeric()).count()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        anchor::AnchorMap,
+        decoding::FontRegistry,
+        filter::Filter,
+        links::Links,
+        normalize::Normalizer,
+        split::{self, WorkMap},
+        toc::Toc,
+        token::Name,
+    };
+
+    use super::*;
+
+    #[test]
+    fn links_to_filtered_pages() {
+        let toc = Toc {
+            entries: Toc::ingest(["Werk".to_owned()].map(Ok), &[10]).unwrap(),
+        };
+        let filter = Filter {
+            pages: Some(1..=3),
+            ..Default::default()
+        };
+        let works = split::split(&toc, filter.select(&toc).unwrap(), 1);
+        let mut works = WorkMap::new(&works);
+        works.enter(0);
+
+        let (fonts, normalizer, anchors): (FontRegistry, Normalizer, AnchorMap) = Default::default();
+        let links = Links { toc: &toc, anchors: &anchors, page_count: 9 };
+        let ctx = Context {
+            works: &works,
+            fonts: &fonts,
+            normalizer: &normalizer,
+            links: &links,
+        };
+
+        let lexed = [
+            Token::AutoLink(2),
+            Token::AutoLink(7),
+            Token::PageLink { page_number: 0, name: Name { data: "bild.jpg".to_owned() } },
+            Token::EndOfPage,
+        ];
+
+        let mut out = String::new();
+        write_page(&toc.entries[0], 1, &lexed, &ctx, &mut Default::default(), &mut out).unwrap();
+
+        assert!(out.contains("@page2"));
+        assert!(!out.contains("@page7"));
+        assert!(out.contains('7'));
+    }
+
+    fn word(s: &str, space_at_end: bool) -> Token {
+        Token::Word {
+            space_at_end,
+            data: decoding::encode_vlado(s).unwrap(),
+        }
+    }
+
+    /// Write consecutive pages, each into a string of its own.
+    fn written(pages: &[&[Token]]) -> Vec<String> {
+        let toc = Toc {
+            entries: Toc::ingest(["Werk".to_owned()].map(Ok), &[10]).unwrap(),
+        };
+        let (works, fonts, normalizer, anchors): (WorkMap, FontRegistry, Normalizer, AnchorMap) =
+            Default::default();
+        let links = Links { toc: &toc, anchors: &anchors, page_count: 9 };
+        let ctx = Context {
+            works: &works,
+            fonts: &fonts,
+            normalizer: &normalizer,
+            links: &links,
+        };
+        let mut carry = encoder::Carry::default();
+
+        pages
+            .iter()
+            .enumerate()
+            .map(|(i, lexed)| {
+                let mut out = String::new();
+                write_page(&toc.entries[0], i + 1, lexed, &ctx, &mut carry, &mut out).unwrap();
+                out
+            })
+            .collect()
+    }
+
+    #[test]
+    fn normalization_across_words() {
+        let lexed = [word("Cafe", false), word("\u{301}", true), word("*noir*", false), Token::EndOfPage];
+        let out = written(&[&lexed]).remove(0);
+
+        assert!(out.contains("Caf\u{e9} \\*noir\\*"), "{:?}", out);
+    }
+
+    #[test]
+    fn hyphenation_across_pages() {
+        let first = [word("Die", true), word("Hoff", false), Token::HypenAtEol, Token::EndOfPage];
+        let second = [word("nung", true), word("Bäk", false), Token::HyphenCK, Token::EndOfPage];
+        let third = [word("ker", true), word("backt", false), Token::EndOfPage];
+
+        let out = written(&[&first, &second, &third]);
+
+        assert!(out[0].contains("Die \n") && !out[0].contains("Hoff"), "{:?}", out[0]);
+        assert!(out[1].contains("Hoffnung \n") && !out[1].contains("Bäk"), "{:?}", out[1]);
+        assert!(out[2].contains("Bäcker backt"), "{:?}", out[2]);
+    }
+
+    #[test]
+    fn hyphenation_within_page() {
+        let lexed = [
+            word("Hoff", false),
+            Token::HypenAtEol,
+            Token::SoftCarriageReturn,
+            word("nung", true),
+            word("Zuk", false),
+            Token::HyphenCK,
+            Token::SoftCarriageReturn,
+            word("ker", false),
+            Token::EndOfPage,
+        ];
+
+        let out = written(&[&lexed]).remove(0);
+        assert!(out.contains("Hoffnung Zucker"), "{:?}", out);
+    }
+}